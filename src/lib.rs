@@ -1,10 +1,12 @@
+use indexmap::IndexMap;
 use regex::Regex;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::{self, File},
     io::BufReader,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 
 pub struct ValidationError {
@@ -14,7 +16,35 @@ pub struct ValidationError {
     pub message: String,
 }
 
-pub type Typeshed = HashMap<String, CommentedType>;
+/// Custom types by name, in declaration order.
+/// This is an `IndexMap` since 0.2, it was a `HashMap` before.
+pub type Typeshed = IndexMap<String, CommentedType>;
+
+/// A type implemented in Rust, usable by name in schemas once registered in the Formats passed to load_with_formats.
+pub trait Format {
+    /// Returns the error message to report if value does not conform to the format.
+    fn validate(&self, value: &Value) -> Result<(), String>;
+
+    /// JSON Schema keywords for this format, added to `"format": "<name>"`.
+    fn to_jsonschema(&self) -> serde_json::Map<String, Value> {
+        serde_json::Map::new()
+    }
+}
+
+impl<F: Fn(&Value) -> Result<(), String>> Format for F {
+    fn validate(&self, value: &Value) -> Result<(), String> {
+        self(value)
+    }
+}
+
+impl std::fmt::Debug for dyn Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Format")
+    }
+}
+
+/// Registry of formats, by the name they are used with in schemas.
+pub type Formats = IndexMap<String, Rc<dyn Format>>;
 
 #[derive(Debug, Clone)]
 pub enum Type {
@@ -31,22 +61,223 @@ pub enum Type {
     DateTime,
     Time,
     HTML,
+    /// Written "html allowing p, em, a[href]": HTML with only these elements, and attributes in brackets
+    HTMLPolicy(IndexMap<String, Vec<String>>),
     URL,
-    /// Object(values, additional properties allowed ?)
-    Object(HashMap<String, CommentedType>, bool),
+    /// Relative reference such as ../images/cover.png, without a scheme
+    RelativeURL,
+    /// Absolute URL or relative file path
+    URLOrPath,
+    /// Only #rrggbb or #rgb
+    HexColor,
+    /// Any color with no transparency
+    OpaqueColor,
+    /// CSS color keyword, such as rebeccapurple
+    NamedColor,
+    Email,
+    UUID,
+    IPv4,
+    IPv6,
+    /// IPv4 or IPv6 address
+    IP,
+    /// Network range such as 10.0.0.0/8
+    CIDR,
+    Hostname,
+    /// Semantic version, such as 1.2.3-beta
+    SemVer,
+    /// Version requirement as understood by Cargo, such as ^1.2, <2
+    SemVerRange,
+    /// Lowercase letters and digits separated by single hyphens
+    Slug,
+    /// BCP 47 language tag, such as fr or en-GB
+    LanguageTag,
+    MIMEType,
+    /// ISO 8601 duration, such as P1DT12H
+    Duration,
+    /// IANA time zone name such as Europe/Paris, or UTC offset such as +02:00
+    Timezone,
+    Object(ObjectType),
     Array(Box<CommentedType>),
     FixedSizeArray(Vec<Box<CommentedType>>),
-    OneOf(Vec<CommentedType>),
+    /// First-match semantics: the value is valid as soon as one of the types accepts it
+    AnyOf(Vec<CommentedType>),
+    /// The value must be accepted by exactly one of the types
+    ExactlyOneOf(Vec<CommentedType>),
     AllOf(Vec<CommentedType>),
     RegexPattern(Regex),
     Literal(serde_json::Value),
     LiteralString(String),
     /// Written {"(one of literally)": ["a", "b"]} shortcut for {"(one of)": ["literally a", "literally b", …]}
     Enum(Vec<serde_json::Value>),
-    Custom(String, Box<CommentedType>),
+    /// Written "reference to .media.*.id": the value must be one of the values found at that path, where `*` stands for any key or index.
+    /// The path is looked up from the nearest enclosing object that has its first key.
+    Reference(Vec<String>),
+    /// Written "same as key": a string equal to the key under which the enclosing object is stored
+    SameAsKey,
+    /// Registered from Rust, see Formats
+    Format(String, Rc<dyn Format>),
+    /// Custom(name, type arguments), resolved against the typeshed at validation time so that types can refer to themselves
+    Custom(String, Vec<Type>),
+    /// Generic(type parameters, body), declared in the typeshed as "translated<T>": {"fr": "T", "en": "T"}
+    Generic(Vec<String>, Box<Type>),
+    /// A type parameter, only found in the body of a generic type
+    Parameter(String),
+    /// TaggedUnion(discriminator property, types by tag), written {"(tagged by)": "type", "image": {…}, "video": {…}}
+    TaggedUnion(String, IndexMap<String, CommentedType>),
+    /// Written {"(not)": "literally TODO"} or "not matches regex ^_"
+    Not(Box<CommentedType>),
+    /// Written "maybe date" or "date or null"
+    Nullable(Box<Type>),
+    /// Constrained(base type, constraints), e.g. "integer between 1 and 10"
+    Constrained(Box<Type>, Vec<Constraint>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjectType {
+    /// Explicitly named properties
+    pub properties: IndexMap<String, CommentedType>,
+    /// Written "(key type, key documentation)": "value type", tried in declaration order
+    pub generic_keys: Vec<GenericKey>,
+    pub optional_properties: HashSet<String>,
+    /// Written "wip": "boolean = false"
+    pub defaults: IndexMap<String, Value>,
+    pub additional_properties: bool,
+    /// Written "(rules)": ["finished requires started", "exactly one of url, file", "wip: true forbids finished"]
+    pub rules: Vec<Rule>,
+}
+
+/// A condition on a property of an object: Condition(property, value).
+/// Without a value, the condition holds when the property is present.
+#[derive(Debug, Clone)]
+pub struct Condition(pub String, pub Option<Value>);
+
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Requires(condition, properties required when the condition holds)
+    Requires(Condition, Vec<String>),
+    /// Forbids(condition, properties forbidden when the condition holds)
+    Forbids(Condition, Vec<String>),
+    ExactlyOneOf(Vec<String>),
+    /// Contrast(color property, other color property, minimum contrast ratio)
+    Contrast(String, String, f64),
+}
+
+impl Condition {
+    fn holds(&self, map: &serde_json::Map<String, Value>) -> bool {
+        match &self.1 {
+            None => map.contains_key(&self.0),
+            Some(value) => map.get(&self.0) == Some(value),
+        }
+    }
+}
+
+impl core::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.1 {
+            None => write!(f, "`{}` is present", self.0),
+            Some(value) => write!(f, "`{}` is {}", self.0, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenericKey {
+    /// The key as written in the schema, parentheses included
+    pub pattern: String,
+    pub key: CommentedType,
+    pub value: CommentedType,
 }
 
-fn warn(txt: &'static str) -> () {
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    Minimum(f64),
+    ExclusiveMinimum(f64),
+    Maximum(f64),
+    ExclusiveMaximum(f64),
+    MultipleOf(f64),
+    /// Minimum number of characters
+    MinLength(usize),
+    /// Maximum number of characters
+    MaxLength(usize),
+    MinItems(usize),
+    MaxItems(usize),
+    UniqueItems,
+    /// No two items of an array, or values of an object, have the same value at this path
+    UniqueBy(Vec<String>),
+    After(Moment),
+    Before(Moment),
+    NotBefore(Moment),
+    NotAfter(Moment),
+    /// Schemes allowed in a URL, written "url with scheme https or http"
+    Schemes(Vec<String>),
+    /// Host of a URL, written "url on host example.com", or "url on host *.example.com" for any of its subdomains
+    Host(String),
+}
+
+/// A point in time that dates and datetimes are compared to
+#[derive(Debug, Clone)]
+pub enum Moment {
+    /// An ISO 8601 date or datetime, as written in the schema
+    At(String),
+    /// Written ".started": the date found at that path, looked up like references
+    Path(Vec<String>),
+    Now,
+}
+
+impl core::fmt::Display for Moment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Moment::At(moment) => write!(f, "{}", moment),
+            Moment::Path(path) => write!(f, ".{}", path.join(".")),
+            Moment::Now => write!(f, "now"),
+        }
+    }
+}
+
+/// "`a` is" or "`a`, `b` are"
+fn is_or_are(subjects: &[String]) -> String {
+    if subjects.len() == 1 {
+        format!("{} is", subjects[0])
+    } else {
+        format!("{} are", subjects.join(", "))
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+impl core::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constraint::Minimum(n) => write!(f, "at least {}", n),
+            Constraint::ExclusiveMinimum(n) => write!(f, "above {}", n),
+            Constraint::Maximum(n) => write!(f, "at most {}", n),
+            Constraint::ExclusiveMaximum(n) => write!(f, "below {}", n),
+            Constraint::MultipleOf(n) => write!(f, "multiple of {}", n),
+            Constraint::MinLength(n) => write!(f, "of at least {}", plural(*n, "character")),
+            Constraint::MaxLength(n) => write!(f, "of at most {}", plural(*n, "character")),
+            Constraint::MinItems(n) => write!(f, "with at least {}", plural(*n, "item")),
+            Constraint::MaxItems(n) => write!(f, "with at most {}", plural(*n, "item")),
+            Constraint::UniqueItems => write!(f, "with unique items"),
+            Constraint::UniqueBy(path) => write!(f, "unique by .{}", path.join(".")),
+            Constraint::After(Moment::Now) => write!(f, "in the future"),
+            Constraint::Before(Moment::Now) => write!(f, "in the past"),
+            Constraint::After(moment) => write!(f, "after {}", moment),
+            Constraint::Before(moment) => write!(f, "before {}", moment),
+            Constraint::NotBefore(moment) => write!(f, "not before {}", moment),
+            Constraint::NotAfter(moment) => write!(f, "not after {}", moment),
+            Constraint::Schemes(schemes) => write!(f, "with scheme {}", schemes.join(" or ")),
+            Constraint::Host(host) => write!(f, "on host {}", host),
+        }
+    }
+}
+
+fn warn(txt: &'static str) {
     println!("WARN: {}", txt)
 }
 
@@ -72,12 +303,54 @@ impl Type {
             Type::DateTime => "datetime".to_owned(),
             Type::Time => "time".to_owned(),
             Type::HTML => "html".to_owned(),
+            Type::HTMLPolicy(allowed) => format!(
+                "html allowing {}",
+                allowed
+                    .iter()
+                    .map(|(element, attributes)| format!(
+                        "{}{}",
+                        element,
+                        attributes
+                            .iter()
+                            .map(|attribute| format!("[{}]", attribute))
+                            .collect::<String>()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Type::URL => "url".to_owned(),
-            Type::Object(_, _) => "object".to_owned(),
+            Type::RelativeURL => "relative url".to_owned(),
+            Type::URLOrPath => "url or path".to_owned(),
+            Type::HexColor => "hex color".to_owned(),
+            Type::OpaqueColor => "opaque color".to_owned(),
+            Type::NamedColor => "named color".to_owned(),
+            Type::Email => "email".to_owned(),
+            Type::UUID => "uuid".to_owned(),
+            Type::IPv4 => "ipv4".to_owned(),
+            Type::IPv6 => "ipv6".to_owned(),
+            Type::IP => "ip".to_owned(),
+            Type::CIDR => "cidr".to_owned(),
+            Type::Hostname => "hostname".to_owned(),
+            Type::SemVer => "semver".to_owned(),
+            Type::SemVerRange => "semver range".to_owned(),
+            Type::Slug => "slug".to_owned(),
+            Type::LanguageTag => "language tag".to_owned(),
+            Type::MIMEType => "mime type".to_owned(),
+            Type::Duration => "duration".to_owned(),
+            Type::Timezone => "timezone".to_owned(),
+            Type::Object(_) => "object".to_owned(),
             Type::Array(_) => "array".to_owned(),
             Type::FixedSizeArray(_) => "array".to_owned(),
-            Type::OneOf(types) => format!(
-                "one of {}",
+            Type::AnyOf(types) => format!(
+                "any of {}",
+                types
+                    .iter()
+                    .map(|t| t.0.type_name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Type::ExactlyOneOf(types) => format!(
+                "exactly one of {}",
                 types
                     .iter()
                     .map(|t| t.0.type_name())
@@ -92,11 +365,43 @@ impl Type {
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
-            Type::RegexPattern(_) => "regex pattern".to_owned(),
+            Type::TaggedUnion(discriminator, types) => format!(
+                "one of {} tagged by {}",
+                types.keys().cloned().collect::<Vec<_>>().join(", "),
+                discriminator
+            ),
+            Type::RegexPattern(regex) => format!("string matching regex {}", regex),
             Type::Literal(value) => serde_type_name(value),
-            Type::LiteralString(_) => "string".to_owned(),
+            Type::LiteralString(s) => format!("literally {:?}", s),
+            Type::Not(typ) => format!("not {}", typ.0.type_name()),
             Type::Enum(_) => "enum".to_owned(),
-            Type::Custom(name, _) => name.clone(),
+            Type::Reference(path) => format!("reference to .{}", path.join(".")),
+            Type::SameAsKey => "same as key".to_owned(),
+            Type::Format(name, _) => name.clone(),
+            Type::Custom(name, arguments) if arguments.is_empty() => name.clone(),
+            Type::Custom(name, arguments) => format!(
+                "{}<{}>",
+                name,
+                arguments
+                    .iter()
+                    .map(|t| t.type_name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Type::Generic(parameters, body) => {
+                format!("{} for any {}", body.type_name(), parameters.join(", "))
+            }
+            Type::Parameter(name) => name.clone(),
+            Type::Nullable(base) => format!("{} or null", base.type_name()),
+            Type::Constrained(base, constraints) => format!(
+                "{} {}",
+                base.type_name(),
+                constraints
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            ),
         }
     }
 }
@@ -149,14 +454,18 @@ fn load_type(
         },
         Value::Bool(_) | Value::Null | Value::Number(_) => Type::Literal(value),
         Value::String(s) => {
-            let typestring = match s.split_once(", ") {
-                Some((typestr, doc)) => {
-                    documentation = doc.clone().to_string();
+            let typestring = match split_documentation(&s) {
+                (typestr, Some(doc)) => {
+                    documentation = doc.to_string();
                     typestr.to_owned()
                 }
-                None => s.to_string(),
+                (typestr, None) => typestr.to_string(),
             };
             match typestring.as_str() {
+                // Registered formats take precedence over builtin types of the same name
+                _ if matches!(custom_types.get(&typestring), Some((Type::Format(_, _), _))) => {
+                    Type::Custom(typestring.to_string(), Vec::new())
+                }
                 "string" => Type::String,
                 "number" => Type::Number,
                 "float" => Type::Float,
@@ -169,35 +478,213 @@ fn load_type(
                 "empty string" => Type::LiteralString("".to_owned()),
                 "time" => Type::Time,
                 "color" => Type::Color,
+                "hex color" => Type::HexColor,
+                "opaque color" => Type::OpaqueColor,
+                "named color" => Type::NamedColor,
                 "any" => Type::Any,
                 "url" => Type::URL,
+                "relative url" => Type::RelativeURL,
+                "url or path" => Type::URLOrPath,
+                "email" => Type::Email,
+                "uuid" => Type::UUID,
+                "ipv4" => Type::IPv4,
+                "ipv6" => Type::IPv6,
+                "ip" => Type::IP,
+                "cidr" => Type::CIDR,
+                "hostname" => Type::Hostname,
+                "semver" => Type::SemVer,
+                "semver range" => Type::SemVerRange,
+                "slug" => Type::Slug,
+                "language tag" => Type::LanguageTag,
+                "mime type" => Type::MIMEType,
+                "duration" => Type::Duration,
+                "timezone" => Type::Timezone,
                 "html" => Type::HTML,
                 "null" => Type::Literal(Value::Null),
+                "same as key" => Type::SameAsKey,
                 _ if typestring.starts_with("literally ") => {
                     Type::LiteralString(typestring["literally ".len()..].to_string())
                 }
                 _ if typestring.starts_with("just ") => {
                     Type::LiteralString(typestring["just ".len()..].to_string())
                 }
-                _ if typestring.starts_with("'") && typestring.ends_with("'") -> {
-                    Type::LiteralString(typestring[1..typestring.len()-1].to_string())
+                _ if typestring.starts_with("'") && typestring.ends_with("'") => {
+                    Type::LiteralString(typestring[1..typestring.len() - 1].to_string())
+                }
+                _ if typestring.starts_with("not ") && !custom_types.contains_key(&typestring) => {
+                    Type::Not(Box::new(load_type(
+                        Value::String(typestring["not ".len()..].to_string()),
+                        custom_types,
+                    )?))
+                }
+                _ if typestring.starts_with("maybe ")
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    Type::Nullable(Box::new(
+                        load_type(
+                            Value::String(typestring["maybe ".len()..].to_string()),
+                            custom_types,
+                        )?
+                        .0,
+                    ))
+                }
+                _ if typestring.ends_with(" or null")
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    Type::Nullable(Box::new(
+                        load_type(
+                            Value::String(
+                                typestring[..typestring.len() - " or null".len()].to_string(),
+                            ),
+                            custom_types,
+                        )?
+                        .0,
+                    ))
+                }
+                _ if ["number ", "integer ", "float "]
+                    .iter()
+                    .any(|prefix| typestring.starts_with(prefix))
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    let (base, constraints) = typestring.split_once(' ').unwrap();
+                    Type::Constrained(
+                        Box::new(load_type(Value::String(base.to_owned()), custom_types)?.0),
+                        parse_numeric_constraints(constraints)?,
+                    )
+                }
+                _ if ["date ", "datetime "]
+                    .iter()
+                    .any(|prefix| typestring.starts_with(prefix))
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    let (base, constraints) = typestring.split_once(' ').unwrap();
+                    Type::Constrained(
+                        Box::new(load_type(Value::String(base.to_owned()), custom_types)?.0),
+                        parse_temporal_constraints(constraints)?,
+                    )
+                }
+                _ if (typestring.starts_with("url with ") || typestring.starts_with("url on "))
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    Type::Constrained(
+                        Box::new(Type::URL),
+                        parse_url_constraints(&typestring["url ".len()..])?,
+                    )
+                }
+                _ if typestring.starts_with("html allowing ") => Type::HTMLPolicy(
+                    typestring["html allowing ".len()..]
+                        .split(", ")
+                        .map(|spec| match parse_tag_spec(spec) {
+                            Some(allowed) => Ok(allowed),
+                            None => Err(format!(
+                                "Invalid element {:?} in {:?}, expected an HTML element name followed by allowed attributes, as in a[href][title]",
+                                spec, s
+                            )),
+                        })
+                        .collect::<Result<_, _>>()?,
+                ),
+                _ if typestring.starts_with("reference to ") => {
+                    match typestring["reference to ".len()..].strip_prefix('.') {
+                        Some(path) if !path.split('.').any(str::is_empty) => {
+                            Type::Reference(path.split('.').map(str::to_owned).collect())
+                        }
+                        _ => {
+                            return Err(format!(
+                                "References need a path such as .media.*.id, in {:?}",
+                                s
+                            )
+                            .into())
+                        }
+                    }
                 }
                 _ if typestring.starts_with("matches regex ") => Type::RegexPattern(Regex::new(
                     typestring.strip_prefix("matches regex ").unwrap(),
                 )?),
-                _ if custom_types.contains_key(&typestring) => Type::Custom(
-                    typestring.to_string(),
-                    Box::new(custom_types[&typestring].clone()),
-                ),
+                _ if typestring.starts_with("non-empty ")
+                    && !custom_types.contains_key(&typestring) =>
+                {
+                    let (base, mut constraints) = match load_type(
+                        Value::String(typestring["non-empty ".len()..].to_string()),
+                        custom_types,
+                    )?
+                    .0
+                    {
+                        Type::Constrained(base, constraints) => (base, constraints),
+                        base => (Box::new(base), Vec::new()),
+                    };
+                    if !is_string_type(&base, custom_types) {
+                        return Err(format!("Only strings can be non-empty, in {:?}", s).into());
+                    }
+                    constraints.insert(0, Constraint::MinLength(1));
+                    Type::Constrained(base, constraints)
+                }
+                _ if typestring.ends_with('>') && typestring.contains('<') => {
+                    let (name, mut arguments) =
+                        typestring[..typestring.len() - 1].split_once('<').unwrap();
+                    let arity = match custom_types.get(name) {
+                        Some((Type::Generic(parameters, _), _)) => parameters.len(),
+                        Some(_) => return Err(format!("Type `{}` is not generic", name).into()),
+                        None => return Err(format!("Invalid type {:?}", s).into()),
+                    };
+                    let mut types = Vec::new();
+                    loop {
+                        let (argument, rest) = split_documentation(arguments);
+                        types.push(load_type(Value::String(argument.to_owned()), custom_types)?.0);
+                        match rest {
+                            Some(rest) => arguments = rest,
+                            None => break,
+                        }
+                    }
+                    if types.len() != arity {
+                        return Err(format!(
+                            "Generic type `{}` takes {}, but got {} in {:?}",
+                            name,
+                            plural(arity, "type argument"),
+                            types.len(),
+                            s
+                        )
+                        .into());
+                    }
+                    Type::Custom(name.to_owned(), types)
+                }
+                _ if custom_types.contains_key(&typestring) => match &custom_types[&typestring].0 {
+                    Type::Parameter(name) => Type::Parameter(name.clone()),
+                    Type::Generic(parameters, _) => {
+                        return Err(format!(
+                            "Generic type `{}` needs type arguments, as in {}<{}>",
+                            typestring,
+                            typestring,
+                            parameters.join(", ")
+                        )
+                        .into())
+                    }
+                    _ => Type::Custom(typestring.to_string(), Vec::new()),
+                },
+                _ if typestring.contains(" of ") => {
+                    let (base, constraints) = typestring.rsplit_once(" of ").unwrap();
+                    let base = load_type(Value::String(base.to_owned()), custom_types)?.0;
+                    if !is_string_type(&base, custom_types) {
+                        return Err(format!(
+                            "Length constraints only apply to strings, in {:?}",
+                            s
+                        )
+                        .into());
+                    }
+                    Type::Constrained(Box::new(base), parse_length_constraints(constraints)?)
+                }
                 _ => return Err(format!("Invalid type {:?}", s).into()),
             }
         }
-        Value::Object(map) => {
+        Value::Object(mut map) => {
             if map.is_empty() {
                 Type::Literal(value.clone())
             } else {
-                match map.keys().next().unwrap().as_str() {
-                    "(one of)" | "(all of)" if map.len() == 1 => {
+                // Custom types can be declared next to any kind of type: {"(types)": …, "(one of)": […]}
+                if let Some(typeshed) = map.shift_remove("(types)") {
+                    load_typeshed(typeshed, custom_types)?;
+                }
+                match map.keys().next().map_or("", String::as_str) {
+                    "(one of)" | "(any of)" | "(exactly one of)" | "(all of)" if map.len() == 1 => {
                         let mut types = Vec::new();
                         if let Some(Value::Array(specs)) = map.values().next() {
                             for value in specs {
@@ -210,12 +697,15 @@ fn load_type(
                             )
                             .into());
                         }
-                        if map.keys().next().unwrap() == "(one of)" {
-                            Type::OneOf(types)
-                        } else {
-                            Type::AllOf(types)
+                        match map.keys().next().unwrap().as_str() {
+                            "(exactly one of)" => Type::ExactlyOneOf(types),
+                            "(all of)" => Type::AllOf(types),
+                            _ => Type::AnyOf(types),
                         }
                     }
+                    "(not)" if map.len() == 1 => {
+                        Type::Not(Box::new(load_type(map["(not)"].clone(), custom_types)?))
+                    }
                     "(one of literally)" | "(enum)" if map.len() == 1 => {
                         let mut literals = Vec::new();
                         for value in map.values() {
@@ -223,31 +713,122 @@ fn load_type(
                         }
                         Type::Enum(literals)
                     }
-                    _ => {
-                        let mut properties = HashMap::new();
-                        let mut additional_properties = false;
-                        if map.contains_key("(types)") {
-                            let value = map["(types)"].clone();
-                            let Some(typeshed) = value.as_object() else {
-                                return Err("Typeshed must be an object mapping type names to types".into());
-                            };
-                            for (key, value) in typeshed {
-                                let loaded_type = load_type(value.clone(), custom_types)?;
-                                custom_types.insert(key.clone(), loaded_type);
+                    _ if map.contains_key("(tagged by)") => {
+                        let Some(Value::String(discriminator)) = map.get("(tagged by)") else {
+                            return Err("(tagged by) must be the name of the tag property".into());
+                        };
+                        let mut types = IndexMap::new();
+                        for (tag, value) in map.iter().filter(|(k, _)| *k != "(tagged by)") {
+                            let (mut typ, documentation) = load_type(value.clone(), custom_types)?;
+                            // Inline objects don't have to repeat their tag
+                            if let Type::Object(ref mut object) = typ {
+                                object
+                                    .properties
+                                    .entry(discriminator.clone())
+                                    .or_insert((Type::LiteralString(tag.clone()), "".to_owned()));
+                            }
+                            types.insert(tag.clone(), (typ, documentation));
+                        }
+                        Type::TaggedUnion(discriminator.clone(), types)
+                    }
+                    _ if map.contains_key("(array of)") => {
+                        let mut constraints = Vec::new();
+                        for (key, value) in map.iter() {
+                            match (key.as_str(), value) {
+                                ("(array of)", _) => {}
+                                ("(unique)", Value::Bool(true)) => {
+                                    constraints.push(Constraint::UniqueItems)
+                                }
+                                ("(unique)", Value::Bool(false)) => {}
+                                ("(unique by)", paths) => {
+                                    constraints.extend(parse_unique_by(paths)?)
+                                }
+                                ("(items)", Value::String(count)) => {
+                                    constraints.extend(parse_items_constraints(count)?)
+                                }
+                                _ => {
+                                    return Err(format!(
+                                        "Invalid key {} in array specification, expected (array of), (items), (unique) or (unique by)",
+                                        key
+                                    )
+                                    .into())
+                                }
                             }
                         }
+                        let elements_type = Type::Array(Box::new(load_type(
+                            map["(array of)"].clone(),
+                            custom_types,
+                        )?));
+                        if constraints.is_empty() {
+                            elements_type
+                        } else {
+                            Type::Constrained(Box::new(elements_type), constraints)
+                        }
+                    }
+                    _ => {
+                        let mut object = ObjectType::default();
+                        let mut constraints = Vec::new();
                         for (key, value) in map {
                             match key.as_str() {
                                 "(additional properties)" | "(additional keys)" => {
-                                    additional_properties = value.as_bool().unwrap();
+                                    object.additional_properties = value.as_bool().unwrap();
+                                }
+                                "(unique by)" => constraints.extend(parse_unique_by(&value)?),
+                                "(import)" => {
+                                    return Err(
+                                        "(import) is only allowed at the top level of a schema"
+                                            .into(),
+                                    )
+                                }
+                                "(rules)" => {
+                                    let rules = match value {
+                                        Value::String(rule) => vec![Value::String(rule)],
+                                        Value::Array(rules) => rules,
+                                        _ => return Err("(rules) must be a list of rules".into()),
+                                    };
+                                    for rule in rules {
+                                        let Value::String(rule) = rule else {
+                                            return Err(format!("Invalid rule {}", rule).into());
+                                        };
+                                        object.rules.push(parse_rule(&rule)?);
+                                    }
+                                }
+                                _ if key.starts_with('(') && key.ends_with(')') => {
+                                    object.generic_keys.push(GenericKey {
+                                        key: load_type(
+                                            Value::String(key[1..key.len() - 1].to_owned()),
+                                            custom_types,
+                                        )?,
+                                        value: load_type(value, custom_types)?,
+                                        pattern: key,
+                                    });
                                 }
-                                "(types)" => {}
                                 _ => {
-                                    properties.insert(key, load_type(value, custom_types)?);
+                                    // A trailing ? marks the property as optional: "footnotes?": …
+                                    let name = match key.strip_suffix('?') {
+                                        Some(name) => {
+                                            object.optional_properties.insert(name.to_owned());
+                                            name.to_owned()
+                                        }
+                                        None => key,
+                                    };
+                                    let (value, default) = split_default(value);
+                                    let typ = load_type(value, custom_types)?;
+                                    if let Some(default) = default {
+                                        check_default(&name, &typ, &default, custom_types)?;
+                                        // Properties with a default value can be omitted
+                                        object.optional_properties.insert(name.clone());
+                                        object.defaults.insert(name.clone(), default);
+                                    }
+                                    object.properties.insert(name, typ);
                                 }
                             }
                         }
-                        Type::Object(properties, additional_properties)
+                        if constraints.is_empty() {
+                            Type::Object(object)
+                        } else {
+                            Type::Constrained(Box::new(Type::Object(object)), constraints)
+                        }
                     }
                 }
             }
@@ -256,110 +837,1677 @@ fn load_type(
     Ok((value, documentation.to_string()))
 }
 
-pub fn load(path: PathBuf) -> Result<Schema, Box<dyn std::error::Error>> {
-    let value = into_serde_value(path)?;
-    let mut custom_types: HashMap<String, CommentedType> = HashMap::new();
-    let (typ, documentation) = load_type(value, &mut custom_types)?;
-    Ok(Schema {
-        types: custom_types,
-        value: (typ, documentation),
-    })
+/// Declares the custom types of a "(types)" typeshed, such as {"translated<T>": {"fr": "T", "en": "T"}}.
+fn load_typeshed(
+    value: Value,
+    custom_types: &mut Typeshed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(typeshed) = value.as_object() else {
+        return Err("Typeshed must be an object mapping type names to types".into());
+    };
+    // Declare every name first, so that types can refer to types declared after them (or to themselves)
+    for key in typeshed.keys() {
+        let (name, parameters) = parse_type_declaration(key);
+        custom_types
+            .entry(name)
+            .or_insert(if parameters.is_empty() {
+                (Type::Any, "".to_owned())
+            } else {
+                (
+                    Type::Generic(parameters, Box::new(Type::Any)),
+                    "".to_owned(),
+                )
+            });
+    }
+    for (key, value) in typeshed {
+        let (name, parameters) = parse_type_declaration(key);
+        if parameters.is_empty() {
+            let loaded_type = load_type(value.clone(), custom_types)?;
+            custom_types.insert(name, loaded_type);
+            continue;
+        }
+        // Type parameters are in scope while loading the body, shadowing custom types of the same name
+        let shadowed = parameters
+            .iter()
+            .map(|parameter| {
+                custom_types.insert(
+                    parameter.clone(),
+                    (Type::Parameter(parameter.clone()), "".to_owned()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let body = load_type(value.clone(), custom_types);
+        for (parameter, shadowed) in parameters.iter().zip(shadowed) {
+            match shadowed {
+                Some(typ) => custom_types.insert(parameter.clone(), typ),
+                None => custom_types.shift_remove(parameter),
+            };
+        }
+        let (body, documentation) = body?;
+        custom_types.insert(
+            name,
+            (Type::Generic(parameters, Box::new(body)), documentation),
+        );
+    }
+    Ok(())
 }
 
-fn serde_type_name(object: &Value) -> String {
-    match object {
-        Value::Array(_) => "array".to_string(),
-        Value::Bool(_) => "boolean".to_string(),
-        Value::Null => "null".to_string(),
-        Value::Number(_) => "number".to_string(),
-        Value::String(_) => "string".to_string(),
-        Value::Object(_) => "object".to_string(),
+/// Parses rules such as "finished requires started", "wip: true forbids finished" or "exactly one of url, file".
+fn parse_rule(rule: &str) -> Result<Rule, Box<dyn std::error::Error>> {
+    let properties = |list: &str| {
+        list.split(", ")
+            .map(|property| property.trim().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let condition = |condition: &str| match condition.split_once(": ") {
+        Some((property, value)) => Condition(
+            property.to_owned(),
+            Some(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))),
+        ),
+        None => Condition(condition.to_owned(), None),
+    };
+    if let Some(list) = rule.strip_prefix("exactly one of ") {
+        Ok(Rule::ExactlyOneOf(properties(list)))
+    } else if let Some(contrast) = rule.strip_prefix("contrast between ") {
+        // "contrast between primary and secondary is at least 4.5"
+        match contrast
+            .rsplit_once(" is at least ")
+            .and_then(|(colors, ratio)| Some((colors.split_once(" and ")?, ratio.parse().ok()?)))
+        {
+            Some(((color, other), ratio)) => {
+                Ok(Rule::Contrast(color.to_owned(), other.to_owned(), ratio))
+            }
+            None => Err(format!(
+                "Invalid rule {:?}, expected \"contrast between … and … is at least …\"",
+                rule
+            )
+            .into()),
+        }
+    } else if let Some((left, right)) = rule.split_once(" requires ") {
+        Ok(Rule::Requires(condition(left), properties(right)))
+    } else if let Some((left, right)) = rule.split_once(" forbids ") {
+        Ok(Rule::Forbids(condition(left), properties(right)))
+    } else {
+        Err(format!(
+            "Invalid rule {:?}, expected \"… requires …\", \"… forbids …\", \"exactly one of …\" or \"contrast between …\"",
+            rule
+        )
+        .into())
     }
 }
 
-pub fn validate_value(
-    file: PathBuf,
-    location: Vec<String>,
-    typ: &CommentedType,
-    value: &Value,
-    custom_types: &mut Typeshed,
-) -> Result<Vec<ValidationError>, Box<dyn std::error::Error>> {
-    println!(
-        "at {}:{}: looking for {} in {} value",
-        file.display(),
-        location.join("."),
-        typ.0,
-        serde_type_name(value)
-    );
-    let mut validation_errors = Vec::new();
-    match (value, &typ.0) {
-        (_, Type::Any) => Ok(Vec::new()),
-        (Value::Array(_), Type::AnyArray) => Ok(Vec::new()),
-        (Value::Array(elements), Type::Array(elements_type)) => {
-            for (i, element) in elements.iter().enumerate() {
-                validation_errors.append(&mut validate_value(
-                    file.clone(),
-                    {
-                        let mut newloc = location.clone();
-                        newloc.push(i.to_string());
-                        newloc
-                    },
-                    &elements_type,
-                    element,
-                    custom_types,
-                )?);
-            }
-            Ok(validation_errors)
+/// Whether typestring is a literal string or a regex, possibly negated or nullable, as in "not literally a = b".
+fn is_literal_form(typestring: &str) -> bool {
+    let mut base = typestring;
+    while let Some(rest) = ["not ", "maybe ", "non-empty "]
+        .iter()
+        .find_map(|prefix| base.strip_prefix(prefix))
+    {
+        base = rest;
+    }
+    ["matches regex ", "literally ", "just ", "'"]
+        .iter()
+        .any(|prefix| base.starts_with(prefix))
+}
+
+/// Splits "type, documentation" on the first ", " that is not part of type arguments, as in "pair<string, integer>, documentation".
+fn split_documentation(s: &str) -> (&str, Option<&str>) {
+    // Literal strings and regexes can contain anything, their documentation starts at the first ", "
+    if is_literal_form(s) {
+        return match s.split_once(", ") {
+            Some((typestring, documentation)) => (typestring, Some(documentation)),
+            None => (s, None),
+        };
+    }
+    let mut depth = 0;
+    let mut previous = ' ';
+    for (i, c) in s.char_indices() {
+        let after = std::mem::replace(&mut previous, c);
+        match c {
+            // Type arguments directly follow the name of the generic type
+            '<' if depth > 0 || after.is_alphanumeric() || after == '_' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            // Lists of elements go on as long as items look like tags: "html allowing p, em, a[href], documentation"
+            ',' if depth == 0
+                && s[i..].starts_with(", ")
+                && s[..i].contains(" allowing ")
+                && parse_tag_spec(s[i + 2..].split(", ").next().unwrap()).is_some() => {}
+            ',' if depth == 0 && s[i..].starts_with(", ") => return (&s[..i], Some(&s[i + 2..])),
+            _ => {}
         }
-        (Value::Array(elements), Type::FixedSizeArray(types)) => {
-            if elements.len() != types.len() {
-                validation_errors.push(ValidationError {
-                    message: format!("Array length does not match fixed size {}", types.len())
-                        .to_owned(),
-                    path: location.clone(),
-                    file: file.clone(),
-                });
-            } else {
-                for (i, element) in elements.iter().enumerate() {
-                    validation_errors.append(&mut validate_value(
-                        file.clone(),
-                        {
-                            let mut newloc = location.clone();
-                            newloc.push(i.to_string());
-                            newloc
-                        },
-                        &types[i],
-                        element,
-                        custom_types,
-                    )?);
+    }
+    (s, None)
+}
+
+/// Elements of the HTML standard, the only ones that can be allowed in "html allowing …" types.
+const HTML_ELEMENTS: &str =
+    "a abbr address area article aside audio b base bdi bdo blockquote body br button \
+    canvas caption cite code col colgroup data datalist dd del details dfn dialog div dl \
+    dt em embed fieldset figcaption figure footer form h1 h2 h3 h4 h5 h6 head header \
+    hgroup hr html i iframe img input ins kbd label legend li link main map mark math menu \
+    meta meter nav noscript object ol optgroup option output p picture pre progress q rp \
+    rt ruby s samp script search section select slot small source span strong style sub \
+    summary sup svg table tbody td template textarea tfoot th thead time title tr track u \
+    ul var video wbr";
+
+/// Parses "a[href][title]" into the element name and its allowed attributes.
+/// Only known HTML elements are accepted, so that documentation after the list is not mistaken for an element.
+fn parse_tag_spec(spec: &str) -> Option<(String, Vec<String>)> {
+    let (name, attributes) = spec.split_once('[').unwrap_or((spec, ""));
+    if !HTML_ELEMENTS
+        .split_whitespace()
+        .any(|element| element.eq_ignore_ascii_case(name))
+    {
+        return None;
+    }
+    let attributes = match attributes {
+        "" if !spec.contains('[') => Vec::new(),
+        _ => attributes
+            .strip_suffix(']')?
+            .split("][")
+            .map(|attribute| {
+                (!attribute.is_empty()
+                    && attribute
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ':'))
+                .then(|| attribute.to_ascii_lowercase())
+            })
+            .collect::<Option<_>>()?,
+    };
+    Some((name.to_ascii_lowercase(), attributes))
+}
+
+/// Offsets, in characters, of the start tags in html, in document order.
+fn start_tag_offsets(html: &str) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut rest = 0;
+    while let Some(i) = html[rest..].find('<') {
+        let at = rest + i;
+        if html[at..].starts_with("<!--") {
+            rest = html[at..]
+                .find("-->")
+                .map_or(html.len(), |end| at + end + 3);
+            continue;
+        }
+        if !html[at + 1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            rest = at + 1;
+            continue;
+        }
+        offsets.push(html[..at].chars().count());
+        // Skip to the end of the tag, as quoted attribute values can contain < and >
+        let mut quote = None;
+        let mut after_equals = false;
+        rest = html.len();
+        for (i, c) in html[at..].char_indices() {
+            match (quote, c) {
+                (Some(q), _) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') if after_equals => quote = Some(c),
+                (None, '>') => {
+                    rest = at + i + 1;
+                    break;
                 }
+                _ => {}
             }
-            Ok(validation_errors)
-        }
-        (Value::Bool(_), Type::Boolean) => Ok(Vec::new()),
-        (Value::Null, Type::Literal(Value::Null)) => Ok(Vec::new()),
-        (Value::Number(_), Type::Number) => Ok(Vec::new()),
-        (Value::Number(_), Type::Float) => {
-            if value.as_f64().unwrap().fract() == 0.0 {
-                validation_errors.push(ValidationError {
-                    message: "Number is not a float".to_owned(),
-                    path: location.clone(),
-                    file,
-                });
+            if !c.is_whitespace() {
+                after_equals = c == '=';
             }
-            Ok(validation_errors)
         }
-        (Value::Number(_), Type::Integer) => {
-            if value.as_f64().unwrap().fract() != 0.0 {
-                validation_errors.push(ValidationError {
-                    message: "Number is not an integer".to_owned(),
-                    path: location.clone(),
-                    file,
-                });
+    }
+    offsets
+}
+
+/// Describes every element and attribute of dom that allowed does not list, with its position in html.
+fn html_policy_violations(
+    html: &str,
+    dom: &html_parser::Dom,
+    allowed: &IndexMap<String, Vec<String>>,
+) -> Vec<String> {
+    fn walk(
+        nodes: &[html_parser::Node],
+        allowed: &IndexMap<String, Vec<String>>,
+        offsets: &mut impl Iterator<Item = usize>,
+        violations: &mut Vec<String>,
+    ) {
+        for node in nodes {
+            let html_parser::Node::Element(element) = node else {
+                continue;
+            };
+            let position = match offsets.next() {
+                Some(offset) => format!(" at character {}", offset + 1),
+                None => "".to_owned(),
+            };
+            let name = element.name.to_ascii_lowercase();
+            match allowed.get(&name) {
+                None => violations.push(format!("Element <{}>{} is not allowed", name, position)),
+                Some(allowed_attributes) => {
+                    let mut attributes = element
+                        .attributes
+                        .keys()
+                        .map(|attribute| attribute.to_ascii_lowercase())
+                        .chain(element.id.as_ref().map(|_| "id".to_owned()))
+                        .chain((!element.classes.is_empty()).then(|| "class".to_owned()))
+                        .collect::<Vec<_>>();
+                    attributes.sort();
+                    for attribute in attributes {
+                        if !allowed_attributes.contains(&attribute) {
+                            violations.push(format!(
+                                "Attribute `{}` of <{}>{} is not allowed",
+                                attribute, name, position
+                            ));
+                        }
+                    }
+                }
             }
-            Ok(validation_errors)
+            walk(&element.children, allowed, offsets, violations);
         }
-        (Value::String(s), Type::Color) => match s.parse::<css_color::Srgb>() {
+    }
+    let mut violations = Vec::new();
+    walk(
+        &dom.children,
+        allowed,
+        &mut start_tag_offsets(html).into_iter(),
+        &mut violations,
+    );
+    violations
+}
+
+/// Splits a typeshed key such as "translated<T>" into its name and type parameters.
+fn parse_type_declaration(key: &str) -> (String, Vec<String>) {
+    match key.strip_suffix('>').and_then(|key| key.split_once('<')) {
+        Some((name, parameters)) => (
+            name.to_owned(),
+            parameters
+                .split(',')
+                .map(|parameter| parameter.trim().to_owned())
+                .collect(),
+        ),
+        None => (key.to_owned(), Vec::new()),
+    }
+}
+
+/// Looks a custom type up in the typeshed, substituting type arguments into generic types.
+fn resolve_custom(
+    name: &str,
+    arguments: &[Type],
+    custom_types: &Typeshed,
+) -> Result<CommentedType, Box<dyn std::error::Error>> {
+    match custom_types.get(name) {
+        None => Err(format!("Unknown custom type `{}`", name).into()),
+        Some((Type::Generic(parameters, body), documentation)) => {
+            if parameters.len() != arguments.len() {
+                return Err(format!(
+                    "Generic type `{}` takes {}, but got {}",
+                    name,
+                    plural(parameters.len(), "type argument"),
+                    arguments.len()
+                )
+                .into());
+            }
+            let bindings = parameters
+                .iter()
+                .map(|p| p.as_str())
+                .zip(arguments)
+                .collect::<HashMap<_, _>>();
+            Ok((substitute(body, &bindings), documentation.clone()))
+        }
+        Some(spec) if arguments.is_empty() => Ok(spec.clone()),
+        Some(_) => Err(format!("Type `{}` is not generic", name).into()),
+    }
+}
+
+/// Replaces type parameters in typ with their bound types.
+fn substitute(typ: &Type, bindings: &HashMap<&str, &Type>) -> Type {
+    map_type(typ, &|t| match t {
+        Type::Parameter(ref name) if bindings.contains_key(name.as_str()) => {
+            bindings[name.as_str()].clone()
+        }
+        t => t,
+    })
+}
+
+/// Rebuilds typ, applying f to every type it contains, innermost types first.
+fn map_type(typ: &Type, f: &dyn Fn(Type) -> Type) -> Type {
+    let commented = |t: &CommentedType| (map_type(&t.0, f), t.1.clone());
+    let mapped = match typ {
+        Type::Custom(name, arguments) => Type::Custom(
+            name.clone(),
+            arguments.iter().map(|t| map_type(t, f)).collect(),
+        ),
+        Type::Generic(parameters, body) => {
+            Type::Generic(parameters.clone(), Box::new(map_type(body, f)))
+        }
+        Type::Object(object) => Type::Object(ObjectType {
+            properties: object
+                .properties
+                .iter()
+                .map(|(key, t)| (key.clone(), commented(t)))
+                .collect(),
+            generic_keys: object
+                .generic_keys
+                .iter()
+                .map(|k| GenericKey {
+                    pattern: k.pattern.clone(),
+                    key: commented(&k.key),
+                    value: commented(&k.value),
+                })
+                .collect(),
+            ..object.clone()
+        }),
+        Type::Array(t) => Type::Array(Box::new(commented(t))),
+        Type::FixedSizeArray(types) => {
+            Type::FixedSizeArray(types.iter().map(|t| Box::new(commented(t))).collect())
+        }
+        Type::AnyOf(types) => Type::AnyOf(types.iter().map(commented).collect()),
+        Type::ExactlyOneOf(types) => Type::ExactlyOneOf(types.iter().map(commented).collect()),
+        Type::AllOf(types) => Type::AllOf(types.iter().map(commented).collect()),
+        Type::TaggedUnion(discriminator, types) => Type::TaggedUnion(
+            discriminator.clone(),
+            types
+                .iter()
+                .map(|(tag, t)| (tag.clone(), commented(t)))
+                .collect(),
+        ),
+        Type::Not(t) => Type::Not(Box::new(commented(t))),
+        Type::Nullable(base) => Type::Nullable(Box::new(map_type(base, f))),
+        Type::Constrained(base, constraints) => {
+            Type::Constrained(Box::new(map_type(base, f)), constraints.clone())
+        }
+        _ => typ.clone(),
+    };
+    f(mapped)
+}
+
+/// Types directly contained in typ.
+fn subtypes(typ: &Type) -> Vec<&Type> {
+    match typ {
+        Type::Object(object) => object
+            .properties
+            .values()
+            .map(|t| &t.0)
+            .chain(
+                object
+                    .generic_keys
+                    .iter()
+                    .flat_map(|k| [&k.key.0, &k.value.0]),
+            )
+            .collect(),
+        Type::Array(t) | Type::Not(t) => vec![&t.0],
+        Type::FixedSizeArray(types) => types.iter().map(|t| &t.0).collect(),
+        Type::AnyOf(types) | Type::ExactlyOneOf(types) | Type::AllOf(types) => {
+            types.iter().map(|t| &t.0).collect()
+        }
+        Type::TaggedUnion(_, types) => types.values().map(|t| &t.0).collect(),
+        Type::Nullable(base) | Type::Constrained(base, _) => vec![base],
+        Type::Custom(_, arguments) => arguments.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether values of typ can only be checked in context: type parameters only get known when the generic type is used,
+/// and "same as key" or references need the enclosing document.
+fn depends_on_context(typ: &Type, custom_types: &Typeshed, seen: &mut Vec<String>) -> bool {
+    match typ {
+        Type::Parameter(_) | Type::SameAsKey | Type::Reference(_) => true,
+        Type::Custom(name, arguments) if !seen.contains(name) => {
+            seen.push(name.clone());
+            resolve_custom(name, arguments, custom_types)
+                .is_ok_and(|(typ, _)| depends_on_context(&typ, custom_types, seen))
+        }
+        _ => subtypes(typ)
+            .into_iter()
+            .any(|typ| depends_on_context(typ, custom_types, seen)),
+    }
+}
+
+/// Rejects default values that do not match the type of their property, as in "integer = hello".
+fn check_default(
+    name: &str,
+    typ: &CommentedType,
+    default: &Value,
+    custom_types: &mut Typeshed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if depends_on_context(&typ.0, custom_types, &mut Vec::new()) {
+        return Ok(());
+    }
+    let errors = check_value(
+        PathBuf::new(),
+        vec![name.to_owned()],
+        typ,
+        default,
+        default,
+        custom_types,
+        false,
+    )?;
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Default value {} of property `{}` does not match its type {}: {}",
+        default,
+        name,
+        typ.0,
+        errors
+            .iter()
+            .map(|e| e.message.clone())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .into())
+}
+
+/// Splits "boolean = false, documentation" into "boolean, documentation" and the default value `false`.
+/// Default values are read as JSON, falling back to a plain string: "string = untitled".
+fn split_default(value: Value) -> (Value, Option<Value>) {
+    let Value::String(s) = &value else {
+        return (value, None);
+    };
+    let (typestring, documentation) = split_documentation(s);
+    // " = " could be part of a regex or a literal string
+    if is_literal_form(typestring) {
+        return (value, None);
+    }
+    let Some((typestring, default)) = typestring.split_once(" = ") else {
+        return (value, None);
+    };
+    let default =
+        serde_json::from_str(default).unwrap_or_else(|_| Value::String(default.to_owned()));
+    let typestring = match documentation {
+        Some(documentation) => format!("{}, {}", typestring, documentation),
+        None => typestring.to_owned(),
+    };
+    (Value::String(typestring), Some(default))
+}
+
+/// Parses constraints such as "between 1 and 10", "at least 0 and multiple of 5" or "below 1.0".
+fn parse_numeric_constraints(
+    constraints: &str,
+) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid numeric constraint {:?}", constraints);
+    let words = constraints.split_whitespace().collect::<Vec<_>>();
+    let number = |i: usize| -> Result<f64, String> {
+        words
+            .get(i)
+            .and_then(|w| w.parse::<f64>().ok())
+            .ok_or_else(invalid)
+    };
+    let mut parsed = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        match &words[i..] {
+            ["and", ..] if !parsed.is_empty() => i += 1,
+            ["between", _, "and", _, ..] => {
+                parsed.push(Constraint::Minimum(number(i + 1)?));
+                parsed.push(Constraint::Maximum(number(i + 3)?));
+                i += 4;
+            }
+            ["at", "least", _, ..] => {
+                parsed.push(Constraint::Minimum(number(i + 2)?));
+                i += 3;
+            }
+            ["at", "most", _, ..] => {
+                parsed.push(Constraint::Maximum(number(i + 2)?));
+                i += 3;
+            }
+            ["above", _, ..] => {
+                parsed.push(Constraint::ExclusiveMinimum(number(i + 1)?));
+                i += 2;
+            }
+            ["below", _, ..] => {
+                parsed.push(Constraint::ExclusiveMaximum(number(i + 1)?));
+                i += 2;
+            }
+            ["multiple", "of", _, ..] => {
+                let divisor = number(i + 2)?;
+                if divisor <= 0.0 {
+                    return Err(invalid().into());
+                }
+                parsed.push(Constraint::MultipleOf(divisor));
+                i += 3;
+            }
+            _ => return Err(invalid().into()),
+        }
+    }
+    if parsed.is_empty() {
+        return Err(invalid().into());
+    }
+    // Bounds as (value, whether the value itself is excluded)
+    let lower_bounds = parsed.iter().filter_map(|constraint| match constraint {
+        Constraint::Minimum(min) => Some((*min, false)),
+        Constraint::ExclusiveMinimum(min) => Some((*min, true)),
+        _ => None,
+    });
+    for (min, min_excluded) in lower_bounds {
+        for constraint in &parsed {
+            let (max, max_excluded) = match constraint {
+                Constraint::Maximum(max) => (*max, false),
+                Constraint::ExclusiveMaximum(max) => (*max, true),
+                _ => continue,
+            };
+            if min > max || (min == max && (min_excluded || max_excluded)) {
+                return Err(format!(
+                    "Constraints {:?} cannot be satisfied, the lower bound excludes the upper bound",
+                    constraints
+                )
+                .into());
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parses constraints on URLs such as "with scheme https or http" or "on host example.com".
+fn parse_url_constraints(constraints: &str) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid URL constraint {:?}", constraints);
+    let words = constraints.split_whitespace().collect::<Vec<_>>();
+    let mut parsed = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        match &words[i..] {
+            ["and", ..] if !parsed.is_empty() => i += 1,
+            ["with", "scheme", scheme, ..] => {
+                let mut schemes = vec![scheme.to_ascii_lowercase()];
+                i += 3;
+                while let ["or", scheme, ..] = &words[i..] {
+                    schemes.push(scheme.to_ascii_lowercase());
+                    i += 2;
+                }
+                parsed.push(Constraint::Schemes(schemes));
+            }
+            ["on", "host", host, ..] => {
+                parsed.push(Constraint::Host(host.to_ascii_lowercase()));
+                i += 3;
+            }
+            _ => return Err(invalid().into()),
+        }
+    }
+    if parsed.is_empty() {
+        return Err(invalid().into());
+    }
+    Ok(parsed)
+}
+
+/// Parses constraints on dates such as "after 2020-01-01", "not before .started", "in the past" or "between 2020-01-01 and now".
+fn parse_temporal_constraints(
+    constraints: &str,
+) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid date constraint {:?}", constraints);
+    let words = constraints.split_whitespace().collect::<Vec<_>>();
+    let moment = |i: usize| -> Result<Moment, String> {
+        match words.get(i) {
+            Some(&"now") | Some(&"today") => Ok(Moment::Now),
+            Some(path) if path.starts_with('.') && path.len() > 1 => Ok(Moment::Path(
+                path[1..].split('.').map(str::to_owned).collect(),
+            )),
+            Some(moment) if parse_moment(moment).is_some() => Ok(Moment::At(moment.to_string())),
+            _ => Err(invalid()),
+        }
+    };
+    let mut parsed = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        match &words[i..] {
+            ["and", ..] if !parsed.is_empty() => i += 1,
+            ["between", _, "and", _, ..] => {
+                parsed.push(Constraint::NotBefore(moment(i + 1)?));
+                parsed.push(Constraint::NotAfter(moment(i + 3)?));
+                i += 4;
+            }
+            ["in", "the", "past", ..] => {
+                parsed.push(Constraint::Before(Moment::Now));
+                i += 3;
+            }
+            ["in", "the", "future", ..] => {
+                parsed.push(Constraint::After(Moment::Now));
+                i += 3;
+            }
+            ["after", _, ..] => {
+                parsed.push(Constraint::After(moment(i + 1)?));
+                i += 2;
+            }
+            ["before", _, ..] => {
+                parsed.push(Constraint::Before(moment(i + 1)?));
+                i += 2;
+            }
+            ["not", "before", _, ..] => {
+                parsed.push(Constraint::NotBefore(moment(i + 2)?));
+                i += 3;
+            }
+            ["not", "after", _, ..] => {
+                parsed.push(Constraint::NotAfter(moment(i + 2)?));
+                i += 3;
+            }
+            _ => return Err(invalid().into()),
+        }
+    }
+    if parsed.is_empty() {
+        return Err(invalid().into());
+    }
+    Ok(parsed)
+}
+
+/// Reads an ISO 8601 datetime, or a date taken at midnight UTC.
+fn parse_moment(s: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let (date, time) = match iso8601::datetime(s) {
+        Ok(datetime) => (datetime.date, datetime.time),
+        Err(_) => (iso8601::date(s).ok()?, iso8601::Time::default()),
+    };
+    let date = match date {
+        iso8601::Date::YMD { year, month, day } => {
+            chrono::NaiveDate::from_ymd_opt(year, month, day)
+        }
+        iso8601::Date::Week { year, ww, d } => chrono::NaiveDate::from_isoywd_opt(
+            year,
+            ww,
+            chrono::Weekday::try_from(d.checked_sub(1)? as u8).ok()?,
+        ),
+        iso8601::Date::Ordinal { year, ddd } => chrono::NaiveDate::from_yo_opt(year, ddd),
+    }?;
+    let offset =
+        chrono::FixedOffset::east_opt(time.tz_offset_hours * 3600 + time.tz_offset_minutes * 60)?;
+    let time = chrono::NaiveTime::from_hms_milli_opt(
+        time.hour,
+        time.minute,
+        time.second,
+        time.millisecond,
+    )?;
+    date.and_time(time).and_local_timezone(offset).single()
+}
+
+/// Parses constraints such as "length 2", "at most 80 characters" or "between 1 and 3 characters".
+fn parse_length_constraints(
+    constraints: &str,
+) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid length constraint {:?}", constraints);
+    let count = |word: &str| word.parse::<usize>().map_err(|_| invalid());
+    match constraints.split_whitespace().collect::<Vec<_>>()[..] {
+        ["length", n] => Ok(vec![
+            Constraint::MinLength(count(n)?),
+            Constraint::MaxLength(count(n)?),
+        ]),
+        ["at", "least", n, "character" | "characters"] => {
+            Ok(vec![Constraint::MinLength(count(n)?)])
+        }
+        ["at", "most", n, "character" | "characters"] => Ok(vec![Constraint::MaxLength(count(n)?)]),
+        ["between", min, "and", max, "characters"] => Ok(vec![
+            Constraint::MinLength(count(min)?),
+            Constraint::MaxLength(count(max)?),
+        ]),
+        _ => Err(invalid().into()),
+    }
+}
+
+/// Parses "(unique by)": "id", or a list of such paths. Paths may start with a dot, as in references: ".metadata.id".
+fn parse_unique_by(paths: &Value) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let paths = match paths {
+        Value::String(path) => vec![path.as_str()],
+        Value::Array(paths) => paths.iter().filter_map(|path| path.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if paths.is_empty() {
+        return Err("(unique by) must be a path such as id, or a list of paths".into());
+    }
+    paths
+        .into_iter()
+        .map(|path| {
+            let path = path.strip_prefix('.').unwrap_or(path);
+            if path.split('.').any(str::is_empty) {
+                Err(format!("Invalid path {:?} in (unique by)", path).into())
+            } else {
+                Ok(Constraint::UniqueBy(
+                    path.split('.').map(str::to_owned).collect(),
+                ))
+            }
+        })
+        .collect()
+}
+
+/// Parses the "(items)" constraint of arrays, written like numeric constraints: "at least 1", "between 2 and 4", …
+fn parse_items_constraints(
+    constraints: &str,
+) -> Result<Vec<Constraint>, Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid items constraint {:?}", constraints);
+    let count = |n: f64| -> Result<usize, String> {
+        if n.fract() == 0.0 && n >= 0.0 {
+            Ok(n as usize)
+        } else {
+            Err(invalid())
+        }
+    };
+    parse_numeric_constraints(constraints)
+        .map_err(|_| invalid())?
+        .into_iter()
+        .map(|constraint| match constraint {
+            Constraint::Minimum(n) => Ok(Constraint::MinItems(count(n)?)),
+            Constraint::ExclusiveMinimum(n) => Ok(Constraint::MinItems(count(n)? + 1)),
+            Constraint::Maximum(n) => Ok(Constraint::MaxItems(count(n)?)),
+            Constraint::ExclusiveMaximum(n) => Ok(Constraint::MaxItems(
+                count(n)?.checked_sub(1).ok_or_else(invalid)?,
+            )),
+            _ => Err(invalid().into()),
+        })
+        .collect()
+}
+
+/// Types whose values are strings, and can thus be constrained in length.
+/// Type parameters and custom types that are not loaded yet are let through,
+/// length constraints on them are checked once the schema is loaded, or when validating.
+fn is_string_type(typ: &Type, custom_types: &Typeshed) -> bool {
+    fn resolves_to_string(typ: &Type, custom_types: &Typeshed, seen: &mut Vec<String>) -> bool {
+        match typ {
+            Type::Custom(name, arguments) => {
+                // Cycles are reported once the schema is loaded
+                if seen.contains(name) {
+                    return true;
+                }
+                seen.push(name.clone());
+                match resolve_custom(name, arguments, custom_types) {
+                    Ok((Type::Any, _)) | Err(_) => true,
+                    Ok((typ, _)) => resolves_to_string(&typ, custom_types, seen),
+                }
+            }
+            Type::Constrained(base, _) => resolves_to_string(base, custom_types, seen),
+            _ => is_builtin_string_type(typ),
+        }
+    }
+    resolves_to_string(typ, custom_types, &mut Vec::new())
+}
+
+/// Builtin types whose values are strings.
+fn is_builtin_string_type(typ: &Type) -> bool {
+    matches!(
+        typ,
+        Type::String
+            | Type::Color
+            | Type::HexColor
+            | Type::OpaqueColor
+            | Type::NamedColor
+            | Type::Date
+            | Type::DateTime
+            | Type::Time
+            | Type::HTML
+            | Type::HTMLPolicy(_)
+            | Type::URL
+            | Type::RelativeURL
+            | Type::URLOrPath
+            | Type::Email
+            | Type::UUID
+            | Type::IPv4
+            | Type::IPv6
+            | Type::IP
+            | Type::CIDR
+            | Type::Hostname
+            | Type::SemVer
+            | Type::SemVerRange
+            | Type::Slug
+            | Type::LanguageTag
+            | Type::MIMEType
+            | Type::Duration
+            | Type::Timezone
+            | Type::RegexPattern(_)
+            | Type::Parameter(_)
+    )
+}
+
+/// Rejects length constraints on custom types that turn out not to be strings once every type is loaded,
+/// as in {"(types)": {"name": "n of length 2", "n": "integer"}}.
+fn check_length_constraints(
+    typ: &Type,
+    custom_types: &Typeshed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Type::Constrained(base, constraints) = typ {
+        if constraints
+            .iter()
+            .any(|c| matches!(c, Constraint::MinLength(_) | Constraint::MaxLength(_)))
+            && !is_string_type(base, custom_types)
+        {
+            return Err(format!(
+                "Length constraints only apply to strings, but {} is not a string type",
+                base
+            )
+            .into());
+        }
+    }
+    for subtype in subtypes(typ) {
+        check_length_constraints(subtype, custom_types)?;
+    }
+    Ok(())
+}
+
+/// Returns an error message if s is not a valid value of the string format typ.
+fn check_string_format(typ: &Type, s: &str) -> Option<String> {
+    match typ {
+        Type::Email if !validator::validate_email(s) => {
+            Some("String is not a valid email address".to_owned())
+        }
+        Type::UUID => uuid::Uuid::try_parse(s)
+            .err()
+            .map(|e| format!("String is not a valid UUID: {}", e)),
+        Type::IPv4 if validator::validate_ip_v6(s) => {
+            Some("String is an IPv6 address, but an IPv4 address is expected".to_owned())
+        }
+        Type::IPv4 if !validator::validate_ip_v4(s) => {
+            Some("String is not a valid IPv4 address".to_owned())
+        }
+        Type::IPv6 if validator::validate_ip_v4(s) => {
+            Some("String is an IPv4 address, but an IPv6 address is expected".to_owned())
+        }
+        Type::IPv6 if !validator::validate_ip_v6(s) => {
+            Some("String is not a valid IPv6 address".to_owned())
+        }
+        Type::IP if !validator::validate_ip(s) => {
+            Some("String is not a valid IP address".to_owned())
+        }
+        Type::CIDR => match s.parse::<ipnet::IpNet>() {
+            Err(_) if !s.contains('/') => Some(format!(
+                "String is not a valid CIDR range: missing prefix length, as in {}/24",
+                s
+            )),
+            Err(e) => Some(format!("String is not a valid CIDR range: {}", e)),
+            Ok(net) if net.trunc() != net => Some(format!(
+                "CIDR range {} has host bits set, the network is {}",
+                s,
+                net.trunc()
+            )),
+            Ok(_) => None,
+        },
+        Type::Hostname => check_hostname(s),
+        Type::SemVer => semver::Version::parse(s)
+            .err()
+            .map(|e| format!("String is not a valid semantic version: {}", e)),
+        Type::SemVerRange => semver::VersionReq::parse(s)
+            .err()
+            .map(|e| format!("String is not a valid version range: {}", e)),
+        Type::Slug => check_slug(s),
+        Type::LanguageTag => match language_tags::LanguageTag::parse(s) {
+            Err(e) => Some(format!("String is not a well-formed language tag: {}", e)),
+            Ok(tag) => tag
+                .validate()
+                .err()
+                .map(|e| format!("Language tag {} is not valid: {}", s, e)),
+        },
+        Type::MIMEType => s
+            .parse::<mime::Mime>()
+            .err()
+            .map(|e| format!("String is not a valid MIME type: {}", e)),
+        Type::Duration => iso8601::duration(s)
+            .err()
+            .map(|e| format!("String is not a valid ISO 8601 duration: {}", e)),
+        Type::Timezone => check_timezone(s),
+        _ => None,
+    }
+}
+
+/// Returns an error message if s is not a relative URL, or for "url or path", neither a URL nor a path.
+fn check_url_reference(typ: &Type, s: &str) -> Option<String> {
+    match url::Url::parse(s) {
+        Ok(_) if matches!(typ, Type::URLOrPath) => None,
+        Ok(url) => Some(format!(
+            "URL is absolute, with scheme {}, but must be relative",
+            url.scheme()
+        )),
+        // Valid absolute URLs are handled above, so this is an absolute URL with errors
+        Err(e) if e != url::ParseError::RelativeUrlWithoutBase => {
+            Some(format!("String is not a valid URL: {}", e))
+        }
+        Err(_) if s.is_empty() => Some("String is empty".to_owned()),
+        Err(_) if matches!(typ, Type::URLOrPath) => None,
+        Err(_) => match s.chars().find(|c| c.is_whitespace() || c.is_control()) {
+            Some(c) => Some(format!(
+                "Relative URL contains {:?}, which must be percent-encoded",
+                c
+            )),
+            None => url::Url::parse("http://example.com/")
+                .unwrap()
+                .join(s)
+                .err()
+                .map(|e| format!("String is not a valid relative URL: {}", e)),
+        },
+    }
+}
+
+/// Returns an error message if s is not a color of the kind typ.
+fn check_color(typ: &Type, s: &str) -> Option<String> {
+    let color = match s.parse::<css_color::Srgb>() {
+        Ok(color) => color,
+        Err(e) => return Some(format!("String is not a valid color: {:?}", e)),
+    };
+    match typ {
+        Type::HexColor
+            if !(s.starts_with('#')
+                && [4, 7].contains(&s.len())
+                && s[1..].chars().all(|c| c.is_ascii_hexdigit())) =>
+        {
+            Some(format!("Color {} is not written #rrggbb or #rgb", s))
+        }
+        Type::OpaqueColor if color.alpha < 1.0 => Some(format!(
+            "Color {} has an opacity of {}, but must be opaque",
+            s, color.alpha
+        )),
+        Type::NamedColor
+            if !s.chars().all(|c| c.is_ascii_alphabetic())
+                || s.eq_ignore_ascii_case("transparent") =>
+        {
+            Some(format!("Color {} is not a named color", s))
+        }
+        _ => None,
+    }
+}
+
+/// WCAG contrast ratio between two colors, from 1 to 21. Transparency is ignored.
+fn contrast_ratio(color: &css_color::Srgb, other: &css_color::Srgb) -> f64 {
+    let luminance = |color: &css_color::Srgb| {
+        let linear = |channel: f32| {
+            let channel = channel as f64;
+            if channel <= 0.04045 {
+                channel / 12.92
+            } else {
+                ((channel + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(color.red) + 0.7152 * linear(color.green) + 0.0722 * linear(color.blue)
+    };
+    let (a, b) = (luminance(color), luminance(other));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn check_timezone(s: &str) -> Option<String> {
+    if s == "Z" || s.parse::<chrono_tz::Tz>().is_ok() {
+        return None;
+    }
+    // Offsets are written +02, +0200 or +02:00
+    if let Some(offset) = s
+        .strip_prefix(['+', '-'])
+        .filter(|offset| offset.is_ascii())
+    {
+        let (hours, minutes) = match offset.len() {
+            2 => (offset, "00"),
+            4 => offset.split_at(2),
+            5 if offset.as_bytes()[2] == b':' => (&offset[..2], &offset[3..]),
+            _ => ("", ""),
+        };
+        let valid = [hours, minutes]
+            .iter()
+            .all(|n| n.len() == 2 && n.chars().all(|c| c.is_ascii_digit()))
+            && hours.parse::<u32>().is_ok_and(|hours| hours <= 14)
+            && minutes.parse::<u32>().is_ok_and(|minutes| minutes < 60);
+        return if valid {
+            None
+        } else {
+            Some(format!("{} is not a valid UTC offset, such as +02:00", s))
+        };
+    }
+    match chrono_tz::TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().eq_ignore_ascii_case(s))
+    {
+        Some(tz) => Some(format!(
+            "Unknown time zone {}, did you mean {}?",
+            s,
+            tz.name()
+        )),
+        None => Some(format!(
+            "String is neither an IANA time zone name nor a UTC offset: {}",
+            s
+        )),
+    }
+}
+
+fn check_slug(s: &str) -> Option<String> {
+    if s.is_empty() {
+        return Some("Slug is empty".to_owned());
+    }
+    if let Some(c) = s
+        .chars()
+        .find(|c| !c.is_ascii_lowercase() && !c.is_ascii_digit() && *c != '-')
+    {
+        return Some(format!(
+            "Slug contains {:?}, only lowercase letters, digits and hyphens are allowed",
+            c
+        ));
+    }
+    if s.starts_with('-') || s.ends_with('-') || s.contains("--") {
+        return Some("Slug has leading, trailing or consecutive hyphens".to_owned());
+    }
+    None
+}
+
+/// Checks s against the hostname syntax of RFC 1123.
+fn check_hostname(s: &str) -> Option<String> {
+    if s.len() > 253 {
+        return Some(format!(
+            "Hostname is {} long, more than the maximum 253",
+            plural(s.len(), "character")
+        ));
+    }
+    for label in s.strip_suffix('.').unwrap_or(s).split('.') {
+        if label.is_empty() {
+            return Some("Hostname has an empty label".to_owned());
+        }
+        if label.len() > 63 {
+            return Some(format!(
+                "Hostname label {:?} is longer than 63 characters",
+                label
+            ));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+        {
+            return Some(format!(
+                "Hostname label {:?} contains the invalid character {:?}",
+                label, c
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Some(format!(
+                "Hostname label {:?} starts or ends with a hyphen",
+                label
+            ));
+        }
+    }
+    None
+}
+
+/// Returns an error message if the date or datetime value is out of the bounds set by constraint.
+/// Bounds referring to a missing or invalid date are ignored, as are invalid values: their type reports them.
+fn check_temporal_constraint(
+    constraint: &Constraint,
+    value: &Value,
+    document: &Value,
+    location: &[String],
+) -> Option<String> {
+    let moment = match constraint {
+        Constraint::After(moment)
+        | Constraint::Before(moment)
+        | Constraint::NotBefore(moment)
+        | Constraint::NotAfter(moment) => moment,
+        _ => return None,
+    };
+    let s = value.as_str()?;
+    let date = parse_moment(s)?;
+    let (bound, described) = match moment {
+        Moment::Now => (chrono::Utc::now().fixed_offset(), "now".to_owned()),
+        Moment::At(at) => (parse_moment(at)?, at.clone()),
+        Moment::Path(path) => {
+            let (_, targets) = resolve_path(document, location, path)?;
+            let target = targets.first()?.1.as_str()?;
+            (parse_moment(target)?, format!("{} ({})", moment, target))
+        }
+    };
+    let (holds, violation) = match constraint {
+        Constraint::After(Moment::Now) => (date > bound, "not in the future".to_owned()),
+        Constraint::Before(Moment::Now) => (date < bound, "not in the past".to_owned()),
+        Constraint::After(_) => (date > bound, format!("not after {}", described)),
+        Constraint::Before(_) => (date < bound, format!("not before {}", described)),
+        Constraint::NotBefore(_) => (date >= bound, format!("before {}", described)),
+        _ => (date <= bound, format!("after {}", described)),
+    };
+    if holds {
+        None
+    } else {
+        Some(format!("Date {} is {}", s, violation))
+    }
+}
+
+/// Returns an error message if value does not satisfy the constraint.
+fn check_constraint(constraint: &Constraint, value: &Value) -> Option<String> {
+    match (constraint, value) {
+        (Constraint::MinLength(1), Value::String(s)) if s.is_empty() => {
+            Some("String is empty".to_owned())
+        }
+        (Constraint::MinLength(min), Value::String(s)) if s.chars().count() < *min => {
+            Some(format!(
+                "String has {}, fewer than the minimum {}",
+                plural(s.chars().count(), "character"),
+                min
+            ))
+        }
+        (Constraint::MaxLength(max), Value::String(s)) if s.chars().count() > *max => {
+            Some(format!(
+                "String has {}, more than the maximum {}",
+                plural(s.chars().count(), "character"),
+                max
+            ))
+        }
+        (Constraint::MinItems(min), Value::Array(elements)) if elements.len() < *min => {
+            Some(format!(
+                "Array has {}, fewer than the minimum {}",
+                plural(elements.len(), "item"),
+                min
+            ))
+        }
+        (Constraint::MaxItems(max), Value::Array(elements)) if elements.len() > *max => {
+            Some(format!(
+                "Array has {}, more than the maximum {}",
+                plural(elements.len(), "item"),
+                max
+            ))
+        }
+        (
+            Constraint::MinLength(_)
+            | Constraint::MaxLength(_)
+            | Constraint::MinItems(_)
+            | Constraint::MaxItems(_)
+            | Constraint::UniqueItems
+            | Constraint::UniqueBy(_)
+            | Constraint::After(_)
+            | Constraint::Before(_)
+            | Constraint::NotBefore(_)
+            | Constraint::NotAfter(_),
+            _,
+        ) => None,
+        (Constraint::Schemes(schemes), Value::String(s)) => {
+            let url = url::Url::parse(s).ok()?;
+            if schemes.iter().any(|scheme| scheme == url.scheme()) {
+                None
+            } else {
+                Some(format!(
+                    "URL has scheme {}, but only {} {} allowed",
+                    url.scheme(),
+                    schemes.join(" or "),
+                    if schemes.len() == 1 { "is" } else { "are" }
+                ))
+            }
+        }
+        (Constraint::Host(host), Value::String(s)) => {
+            let url = url::Url::parse(s).ok()?;
+            let actual = url.host_str().unwrap_or_default();
+            let matches = match host.strip_prefix("*.") {
+                Some(domain) => actual
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.') && subdomain.len() > 1),
+                None => actual == host,
+            };
+            if matches {
+                None
+            } else if actual.is_empty() {
+                Some(format!("URL has no host, but must be on {}", host))
+            } else {
+                Some(format!(
+                    "URL is on host {}, but must be on {}",
+                    actual, host
+                ))
+            }
+        }
+        (Constraint::Schemes(_) | Constraint::Host(_), _) => None,
+        (constraint, value) => {
+            let n = value.as_f64()?;
+            match *constraint {
+                Constraint::Minimum(min) if n < min => {
+                    Some(format!("Number {} is less than the minimum {}", n, min))
+                }
+                Constraint::ExclusiveMinimum(min) if n <= min => {
+                    Some(format!("Number {} is not above {}", n, min))
+                }
+                Constraint::Maximum(max) if n > max => {
+                    Some(format!("Number {} is greater than the maximum {}", n, max))
+                }
+                Constraint::ExclusiveMaximum(max) if n >= max => {
+                    Some(format!("Number {} is not below {}", n, max))
+                }
+                Constraint::MultipleOf(divisor)
+                    if ((n / divisor) - (n / divisor).round()).abs() > 1e-9 =>
+                {
+                    Some(format!("Number {} is not a multiple of {}", n, divisor))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+pub fn load(path: PathBuf) -> Result<Schema, Box<dyn std::error::Error>> {
+    load_with_formats(path, &Formats::new())
+}
+
+/// Loads the schema at path, in which the registered formats can be used as types.
+pub fn load_with_formats(
+    path: PathBuf,
+    formats: &Formats,
+) -> Result<Schema, Box<dyn std::error::Error>> {
+    let mut custom_types: Typeshed = formats
+        .iter()
+        .map(|(name, format)| {
+            (
+                name.clone(),
+                (Type::Format(name.clone(), format.clone()), "".to_owned()),
+            )
+        })
+        .collect();
+    let (typ, documentation) = load_file(&path, &mut custom_types, &mut Vec::new())?;
+    check_loaded_schema(&typ, &custom_types)?;
+    Ok(Schema {
+        types: custom_types,
+        value: (typ, documentation),
+    })
+}
+
+/// Checks that need every custom type to be loaded.
+fn check_loaded_schema(
+    typ: &Type,
+    custom_types: &Typeshed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_unguarded_cycles(custom_types)?;
+    check_expanding_generics(custom_types)?;
+    check_length_constraints(typ, custom_types)?;
+    for (custom_type, _) in custom_types.values() {
+        match custom_type {
+            Type::Generic(_, body) => check_length_constraints(body, custom_types)?,
+            _ => check_length_constraints(custom_type, custom_types)?,
+        }
+    }
+    Ok(())
+}
+
+/// Loads the schema at path, after merging the typeshed of the files it imports into custom_types.
+/// import_chain holds the files currently being loaded, to detect import cycles.
+fn load_file(
+    path: &PathBuf,
+    custom_types: &mut Typeshed,
+    import_chain: &mut Vec<PathBuf>,
+) -> Result<CommentedType, Box<dyn std::error::Error>> {
+    let canonical_path =
+        fs::canonicalize(path).map_err(|e| format!("Cannot load {}: {}", path.display(), e))?;
+    if let Some(start) = import_chain.iter().position(|p| *p == canonical_path) {
+        return Err(format!(
+            "Import cycle: {} -> {}",
+            import_chain[start..]
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> "),
+            canonical_path.display()
+        )
+        .into());
+    }
+    import_chain.push(canonical_path);
+
+    let mut value = into_serde_value(path.clone())?;
+    let imports = match value
+        .as_object_mut()
+        .and_then(|map| map.shift_remove("(import)"))
+    {
+        None => Vec::new(),
+        Some(Value::String(file)) => vec![("".to_owned(), file)],
+        Some(Value::Array(files)) => files
+            .into_iter()
+            .map(|file| match file {
+                Value::String(file) => Ok(("".to_owned(), file)),
+                _ => Err(format!("Invalid import {}, expected a file path", file)),
+            })
+            .collect::<Result<_, _>>()?,
+        // Written {"(import)": {"common": "common.strudach.yaml"}}, types are then available as "common.color"
+        Some(Value::Object(namespaces)) => namespaces
+            .into_iter()
+            .map(|(namespace, file)| match file {
+                Value::String(file) => Ok((format!("{}.", namespace), file)),
+                _ => Err(format!("Invalid import {}, expected a file path", file)),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(other) => return Err(format!("Invalid (import) {}", other).into()),
+    };
+    for (prefix, file) in imports {
+        let imported_path = path.parent().unwrap_or(Path::new("")).join(file);
+        // Registered formats are available in imported files too, under their own name
+        let mut imported_types: Typeshed = custom_types
+            .iter()
+            .filter(|(_, (typ, _))| matches!(typ, Type::Format(_, _)))
+            .map(|(name, typ)| (name.clone(), typ.clone()))
+            .collect();
+        load_file(&imported_path, &mut imported_types, import_chain)
+            .map_err(|e| format!("{}\n  imported from {}", e, path.display()))?;
+        let prefixed = |t: Type| match t {
+            Type::Custom(name, arguments)
+                if imported_types
+                    .get(&name)
+                    .is_some_and(|(typ, _)| !matches!(typ, Type::Format(_, _))) =>
+            {
+                Type::Custom(format!("{}{}", prefix, name), arguments)
+            }
+            t => t,
+        };
+        for (name, (typ, documentation)) in &imported_types {
+            if let Type::Format(_, _) = typ {
+                continue;
+            }
+            custom_types.insert(
+                format!("{}{}", prefix, name),
+                (map_type(typ, &prefixed), documentation.clone()),
+            );
+        }
+    }
+
+    let typ =
+        load_type(value, custom_types).map_err(|e| format!("{}\n  in {}", e, path.display()))?;
+    import_chain.pop();
+    Ok(typ)
+}
+
+/// Custom types that typ refers to without going through an object or an array, i.e. without consuming any part of the validated value.
+fn unguarded_references(typ: &Type) -> Vec<String> {
+    match typ {
+        Type::Custom(name, _) => vec![name.clone()],
+        Type::Generic(_, body) => unguarded_references(body),
+        Type::Not(typ) => unguarded_references(&typ.0),
+        Type::Constrained(base, _) | Type::Nullable(base) => unguarded_references(base),
+        Type::AnyOf(types) | Type::ExactlyOneOf(types) | Type::AllOf(types) => types
+            .iter()
+            .flat_map(|t| unguarded_references(&t.0))
+            .collect(),
+        Type::TaggedUnion(_, types) => types
+            .values()
+            .flat_map(|t| unguarded_references(&t.0))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Rejects custom types such as `{"a": "b", "b": "a"}`, which would make validation loop forever.
+fn check_unguarded_cycles(custom_types: &Typeshed) -> Result<(), Box<dyn std::error::Error>> {
+    fn visit(
+        name: &str,
+        custom_types: &Typeshed,
+        chain: &mut Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(start) = chain.iter().position(|n| n == name) {
+            return Err(format!(
+                "Custom type `{}` refers to itself without going through an object or an array: {} -> {}",
+                name,
+                chain[start..].join(" -> "),
+                name
+            )
+            .into());
+        }
+        let Some(typ) = custom_types.get(name) else {
+            return Ok(());
+        };
+        chain.push(name.to_owned());
+        for reference in unguarded_references(&typ.0) {
+            visit(&reference, custom_types, chain)?;
+        }
+        chain.pop();
+        Ok(())
+    }
+
+    for name in custom_types.keys() {
+        visit(name, custom_types, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
+/// Whether typ refers to the type parameter, directly or through type arguments.
+fn mentions_parameter(typ: &Type, parameter: &str) -> bool {
+    match typ {
+        Type::Parameter(name) => name == parameter,
+        _ => subtypes(typ)
+            .into_iter()
+            .any(|t| mentions_parameter(t, parameter)),
+    }
+}
+
+/// Rejects generic types used with ever bigger type arguments in their own definition, such as
+/// `{"t<T>": {"next?": "t<t<T>>"}}`, which would need infinitely many definitions in JSON Schema.
+fn check_expanding_generics(custom_types: &Typeshed) -> Result<(), Box<dyn std::error::Error>> {
+    // Edges from a type parameter of a generic type, identified by its index, to the type parameters
+    // whose arguments mention it in the body, marked as expanding unless the argument is the parameter itself
+    type Node<'a> = (&'a str, usize);
+    let mut edges: Vec<(Node, Node, bool)> = Vec::new();
+    for (name, (typ, _)) in custom_types {
+        let Type::Generic(parameters, body) = typ else {
+            continue;
+        };
+        let mut stack = vec![&**body];
+        while let Some(typ) = stack.pop() {
+            if let Type::Custom(used, arguments) = typ {
+                if let Some((Type::Generic(..), _)) = custom_types.get(used) {
+                    for (j, argument) in arguments.iter().enumerate() {
+                        for (i, parameter) in parameters.iter().enumerate() {
+                            if mentions_parameter(argument, parameter) {
+                                let expanding =
+                                    !matches!(argument, Type::Parameter(p) if p == parameter);
+                                edges.push(((name, i), (used, j), expanding));
+                            }
+                        }
+                    }
+                }
+            }
+            stack.extend(subtypes(typ));
+        }
+    }
+    for &(from, to, expanding) in &edges {
+        if !expanding {
+            continue;
+        }
+        let mut reached = vec![to];
+        let mut i = 0;
+        while let Some(&node) = reached.get(i) {
+            if node == from {
+                return Err(format!(
+                    "Generic type `{}` is used with ever bigger type arguments in its own definition",
+                    from.0
+                )
+                .into());
+            }
+            for &(source, target, _) in &edges {
+                if source == node && !reached.contains(&target) {
+                    reached.push(target);
+                }
+            }
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// A value inside the validated document, along with its location.
+type Located<'a> = (Vec<String>, &'a Value);
+
+/// Collects the values found at path below node, where `*` stands for any key or index, along with their locations.
+fn values_at<'a>(
+    node: &'a Value,
+    path: &[String],
+    location: Vec<String>,
+    found: &mut Vec<Located<'a>>,
+) {
+    let Some((first, rest)) = path.split_first() else {
+        found.push((location, node));
+        return;
+    };
+    let children: Vec<(String, &Value)> = match node {
+        Value::Object(map) if first == "*" => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        Value::Array(elements) if first == "*" => elements
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect(),
+        Value::Object(map) => map
+            .get(first)
+            .map(|v| (first.clone(), v))
+            .into_iter()
+            .collect(),
+        Value::Array(elements) => first
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| elements.get(i))
+            .map(|v| (first.clone(), v))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+    for (key, child) in children {
+        let mut newloc = location.clone();
+        newloc.push(key);
+        values_at(child, rest, newloc, found);
+    }
+}
+
+/// The node of document at location, if any.
+fn node_at<'a>(document: &'a Value, location: &[String]) -> Option<&'a Value> {
+    location.iter().try_fold(document, |node, key| match node {
+        Value::Object(map) => map.get(key),
+        Value::Array(elements) => key.parse::<usize>().ok().and_then(|i| elements.get(i)),
+        _ => None,
+    })
+}
+
+/// Looks path up from the nearest ancestor of location that has the path's first key, going up to the document's root.
+/// Returns the location of that ancestor and the values found at path below it.
+fn resolve_path<'a>(
+    document: &'a Value,
+    location: &[String],
+    path: &[String],
+) -> Option<(Vec<String>, Vec<Located<'a>>)> {
+    for depth in (0..location.len()).rev() {
+        let Some(ancestor) = node_at(document, &location[..depth]) else {
+            continue;
+        };
+        let mut heads = Vec::new();
+        values_at(ancestor, &path[..1], Vec::new(), &mut heads);
+        if !heads.is_empty() {
+            let mut found = Vec::new();
+            values_at(ancestor, path, location[..depth].to_vec(), &mut found);
+            return Some((location[..depth].to_vec(), found));
+        }
+    }
+    None
+}
+
+fn serde_type_name(object: &Value) -> String {
+    match object {
+        Value::Array(_) => "array".to_string(),
+        Value::Bool(_) => "boolean".to_string(),
+        Value::Null => "null".to_string(),
+        Value::Number(_) => "number".to_string(),
+        Value::String(_) => "string".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+fn type_mismatch(value: &Value, typ: &Type) -> String {
+    format!(
+        "Value has type {}, which does not match type {}",
+        serde_type_name(value),
+        typ.type_name()
+    )
+}
+
+/// Validates value against typ, taking value as the whole document that references are looked up in.
+pub fn validate_value(
+    file: PathBuf,
+    location: Vec<String>,
+    typ: &CommentedType,
+    value: &Value,
+    custom_types: &mut Typeshed,
+) -> Result<Vec<ValidationError>, Box<dyn std::error::Error>> {
+    check_value(file, location, typ, value, value, custom_types, true)
+}
+
+/// Validates value, found at location in document, against typ.
+/// References and "same as key" are checked against document.
+pub fn validate_value_in_document(
+    file: PathBuf,
+    location: Vec<String>,
+    typ: &CommentedType,
+    value: &Value,
+    document: &Value,
+    custom_types: &mut Typeshed,
+) -> Result<Vec<ValidationError>, Box<dyn std::error::Error>> {
+    check_value(file, location, typ, value, document, custom_types, true)
+}
+
+/// Validates value against typ, printing what is being looked for when trace is set.
+fn check_value(
+    file: PathBuf,
+    location: Vec<String>,
+    typ: &CommentedType,
+    value: &Value,
+    document: &Value,
+    custom_types: &mut Typeshed,
+    trace: bool,
+) -> Result<Vec<ValidationError>, Box<dyn std::error::Error>> {
+    if trace {
+        println!(
+            "at {}:{}: looking for {} in {} value",
+            file.display(),
+            location.join("."),
+            typ.0,
+            serde_type_name(value)
+        );
+    }
+    let mut validation_errors = Vec::new();
+    match (value, &typ.0) {
+        (_, Type::Any) => Ok(Vec::new()),
+        (Value::Array(_), Type::AnyArray) => Ok(Vec::new()),
+        (Value::Array(elements), Type::Array(elements_type)) => {
+            for (i, element) in elements.iter().enumerate() {
+                validation_errors.append(&mut check_value(
+                    file.clone(),
+                    {
+                        let mut newloc = location.clone();
+                        newloc.push(i.to_string());
+                        newloc
+                    },
+                    elements_type,
+                    element,
+                    document,
+                    custom_types,
+                    trace,
+                )?);
+            }
+            Ok(validation_errors)
+        }
+        (Value::Array(elements), Type::FixedSizeArray(types)) => {
+            if elements.len() != types.len() {
+                validation_errors.push(ValidationError {
+                    message: format!("Array length does not match fixed size {}", types.len())
+                        .to_owned(),
+                    path: location.clone(),
+                    file: file.clone(),
+                });
+            } else {
+                for (i, element) in elements.iter().enumerate() {
+                    validation_errors.append(&mut check_value(
+                        file.clone(),
+                        {
+                            let mut newloc = location.clone();
+                            newloc.push(i.to_string());
+                            newloc
+                        },
+                        &types[i],
+                        element,
+                        document,
+                        custom_types,
+                        trace,
+                    )?);
+                }
+            }
+            Ok(validation_errors)
+        }
+        (Value::Bool(_), Type::Boolean) => Ok(Vec::new()),
+        (Value::Null, Type::Literal(Value::Null)) => Ok(Vec::new()),
+        (Value::Number(_), Type::Number) => Ok(Vec::new()),
+        (Value::Number(_), Type::Float) => {
+            if value.as_f64().unwrap().fract() == 0.0 {
+                validation_errors.push(ValidationError {
+                    message: "Number is not a float".to_owned(),
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (Value::Number(_), Type::Integer) => {
+            if value.as_f64().unwrap().fract() != 0.0 {
+                validation_errors.push(ValidationError {
+                    message: "Number is not an integer".to_owned(),
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (Value::String(s), Type::Color) => match s.parse::<css_color::Srgb>() {
             Ok(_) => Ok(Vec::new()),
             Err(e) => {
                 validation_errors.push(ValidationError {
@@ -370,8 +2518,29 @@ pub fn validate_value(
                 Ok(validation_errors)
             }
         },
-        (Value::String(s), Type::HTML) => match html_parser::Dom::parse(s) {
-            Ok(_) => Ok(Vec::new()),
+        (Value::String(s), Type::HexColor | Type::OpaqueColor | Type::NamedColor) => {
+            if let Some(message) = check_color(&typ.0, s) {
+                validation_errors.push(ValidationError {
+                    message,
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (Value::String(s), Type::HTML | Type::HTMLPolicy(_)) => match html_parser::Dom::parse(s) {
+            Ok(dom) => {
+                if let Type::HTMLPolicy(allowed) = &typ.0 {
+                    for message in html_policy_violations(s, &dom, allowed) {
+                        validation_errors.push(ValidationError {
+                            message,
+                            path: location.clone(),
+                            file: file.clone(),
+                        });
+                    }
+                }
+                Ok(validation_errors)
+            }
             Err(html_parser::Error::Parsing(e)) => {
                 validation_errors.push(ValidationError {
                     message: format!("String is not valid HTML: {}", e),
@@ -380,9 +2549,7 @@ pub fn validate_value(
                 });
                 Ok(validation_errors)
             }
-            Err(e) => {
-                return Err(format!("Error while validating HTML: {:?}", e).into());
-            }
+            Err(e) => Err(format!("Error while validating HTML: {:?}", e).into()),
         },
         (Value::String(s), Type::Date) => match iso8601::date(s) {
             Ok(_) => Ok(Vec::new()),
@@ -417,6 +2584,16 @@ pub fn validate_value(
                 Ok(validation_errors)
             }
         },
+        (Value::String(s), Type::RelativeURL | Type::URLOrPath) => {
+            if let Some(message) = check_url_reference(&typ.0, s) {
+                validation_errors.push(ValidationError {
+                    message,
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
         (Value::String(s), Type::URL) => {
             if validator::validate_url(s) {
                 Ok(validation_errors)
@@ -429,9 +2606,35 @@ pub fn validate_value(
                 Ok(validation_errors)
             }
         }
+        (
+            Value::String(s),
+            Type::Email
+            | Type::UUID
+            | Type::IPv4
+            | Type::IPv6
+            | Type::IP
+            | Type::CIDR
+            | Type::Hostname
+            | Type::SemVer
+            | Type::SemVerRange
+            | Type::Slug
+            | Type::LanguageTag
+            | Type::MIMEType
+            | Type::Duration
+            | Type::Timezone,
+        ) => {
+            if let Some(message) = check_string_format(&typ.0, s) {
+                validation_errors.push(ValidationError {
+                    message,
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
         (Value::String(_), Type::String) => Ok(Vec::new()),
         (Value::String(s), Type::RegexPattern(regex)) => {
-            if !regex.is_match(&s) {
+            if !regex.is_match(s) {
                 validation_errors.push(ValidationError {
                     message: format!("String does not match regex {}", regex).to_owned(),
                     path: location.clone(),
@@ -453,60 +2656,104 @@ pub fn validate_value(
             }
             Ok(validation_errors)
         }
-        (Value::Object(map), Type::Object(properties, additional_properties)) => {
+        (Value::Object(map), Type::Object(object)) => {
             for (key, value) in map {
-                if properties.contains_key(key) {
-                    validation_errors.append(&mut validate_value(
+                if object.properties.contains_key(key) {
+                    validation_errors.append(&mut check_value(
                         file.clone(),
                         {
                             let mut newloc = location.clone();
                             newloc.push(key.to_string());
                             newloc
                         },
-                        &properties[key],
+                        &object.properties[key],
                         value,
+                        document,
                         custom_types,
+                        trace,
                     )?);
-                    // If there's a generic key in the type's properties, we check that additional keys conform.
-                } else if let Some(generic_key) = properties
-                    .keys()
-                    .find(|k| k.starts_with("(") && k.ends_with(")"))
-                    .map(|k| k.strip_prefix("(").unwrap().strip_suffix(")").unwrap())
-                {
-                    let key_type = load_type(Value::String(generic_key.to_owned()), custom_types)?;
-                    validation_errors.append(&mut validate_value(
+                    continue;
+                }
+                // If there are generic keys in the type's properties, we check that additional keys conform
+                // to the first one (in declaration order) whose key type accepts the key.
+                let mut matching_generic_key = None;
+                let mut key_errors = Vec::new();
+                for generic_key in &object.generic_keys {
+                    let errors = check_value(
                         file.clone(),
                         {
                             let mut newloc = location.clone();
                             newloc.push("(key)".to_string());
                             newloc
                         },
-                        &key_type,
+                        &generic_key.key,
                         &Value::String(key.to_string()),
+                        document,
                         custom_types,
-                    )?);
-                    validation_errors.append(&mut validate_value(
-                        file.clone(),
-                        {
-                            let mut newloc = location.clone();
-                            newloc.push(key.to_string());
-                            newloc
-                        },
-                        &properties[&format!("({})", generic_key)],
-                        value,
-                        custom_types,
-                    )?);
-                } else if !additional_properties {
-                    validation_errors.push(ValidationError {
-                        message: format!("Object has additional property `{}`", key).to_owned(),
-                        path: location.clone(),
-                        file: file.clone(),
-                    });
+                        trace,
+                    )?;
+                    if errors.is_empty() {
+                        matching_generic_key = Some(generic_key);
+                        break;
+                    }
+                    key_errors.extend(errors);
                 }
+                let generic_key = match (matching_generic_key, object.generic_keys.as_slice()) {
+                    (Some(generic_key), _) => generic_key,
+                    (None, _) if object.additional_properties => continue,
+                    (None, []) => {
+                        validation_errors.push(ValidationError {
+                            message: format!("Object has additional property `{}`", key).to_owned(),
+                            path: location.clone(),
+                            file: file.clone(),
+                        });
+                        continue;
+                    }
+                    // With a single generic key, its errors are precise enough to be reported as-is
+                    (None, [generic_key]) => {
+                        validation_errors.append(&mut key_errors);
+                        generic_key
+                    }
+                    (None, _) => {
+                        validation_errors.push(ValidationError {
+                            message: format!(
+                                "Key `{}` does not match any of the generic keys {}",
+                                key,
+                                object
+                                    .generic_keys
+                                    .iter()
+                                    .map(|k| k.pattern.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                            path: {
+                                let mut newloc = location.clone();
+                                newloc.push("(key)".to_string());
+                                newloc
+                            },
+                            file: file.clone(),
+                        });
+                        continue;
+                    }
+                };
+                validation_errors.append(&mut check_value(
+                    file.clone(),
+                    {
+                        let mut newloc = location.clone();
+                        newloc.push(key.to_string());
+                        newloc
+                    },
+                    &generic_key.value,
+                    value,
+                    document,
+                    custom_types,
+                    trace,
+                )?);
             }
-            let missing_keys = properties
+            let missing_keys = object
+                .properties
                 .keys()
-                .filter(|key| !(key.starts_with("(") && key.ends_with(")")))
+                .filter(|key| !object.optional_properties.contains(*key))
                 .filter(|key| !map.contains_key(*key))
                 .collect::<Vec<_>>();
             if !missing_keys.is_empty() {
@@ -529,33 +2776,120 @@ pub fn validate_value(
                     file: file.clone(),
                 });
             }
+            for rule in &object.rules {
+                let message = match rule {
+                    Rule::Requires(condition, required) if condition.holds(map) => {
+                        let missing = required
+                            .iter()
+                            .filter(|property| !map.contains_key(*property))
+                            .map(|property| format!("`{}`", property))
+                            .collect::<Vec<_>>();
+                        if missing.is_empty() {
+                            continue;
+                        }
+                        format!("{}, so {} required", condition, is_or_are(&missing))
+                    }
+                    Rule::Forbids(condition, forbidden) if condition.holds(map) => {
+                        let present = forbidden
+                            .iter()
+                            .filter(|property| map.contains_key(*property))
+                            .map(|property| format!("`{}`", property))
+                            .collect::<Vec<_>>();
+                        if present.is_empty() {
+                            continue;
+                        }
+                        format!("{}, so {} not allowed", condition, is_or_are(&present))
+                    }
+                    Rule::ExactlyOneOf(properties) => {
+                        let present = properties
+                            .iter()
+                            .filter(|property| map.contains_key(*property))
+                            .map(|property| format!("`{}`", property))
+                            .collect::<Vec<_>>();
+                        let expected = properties
+                            .iter()
+                            .map(|property| format!("`{}`", property))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        match present.len() {
+                            1 => continue,
+                            0 => format!("Exactly one of {} must be set, but none is", expected),
+                            _ => format!(
+                                "Exactly one of {} must be set, but {} are",
+                                expected,
+                                present.join(" and ")
+                            ),
+                        }
+                    }
+                    Rule::Contrast(color, other, minimum) => {
+                        let (Some(Value::String(color_value)), Some(Value::String(other_value))) =
+                            (map.get(color), map.get(other))
+                        else {
+                            continue;
+                        };
+                        // Invalid colors are reported by the type of their property
+                        let (Ok(first), Ok(second)) = (
+                            color_value.parse::<css_color::Srgb>(),
+                            other_value.parse::<css_color::Srgb>(),
+                        ) else {
+                            continue;
+                        };
+                        let ratio = contrast_ratio(&first, &second);
+                        if ratio >= *minimum {
+                            continue;
+                        }
+                        format!(
+                            "Contrast between `{}` ({}) and `{}` ({}) is {:.2}:1, below the minimum {}:1",
+                            color, color_value, other, other_value, ratio, minimum
+                        )
+                    }
+                    _ => continue,
+                };
+                validation_errors.push(ValidationError {
+                    message,
+                    path: location.clone(),
+                    file: file.clone(),
+                });
+            }
             Ok(validation_errors)
         }
         (value, Type::AllOf(types)) => {
             for typ in types {
-                validation_errors.append(&mut validate_value(
+                validation_errors.append(&mut check_value(
                     file.clone(),
                     location.clone(),
-                    &typ,
+                    typ,
                     value,
+                    document,
                     custom_types,
+                    trace,
                 )?);
             }
             Ok(validation_errors)
         }
-        (value, Type::OneOf(types)) => {
+        (value, Type::AnyOf(types)) => {
             let mut valid = false;
+            // Errors of the branches are only reported if none of them matches
+            let mut branch_errors = Vec::new();
             for typ in types {
-                let mut errors =
-                    validate_value(file.clone(), location.clone(), &typ, value, custom_types)?;
+                let mut errors = check_value(
+                    file.clone(),
+                    location.clone(),
+                    typ,
+                    value,
+                    document,
+                    custom_types,
+                    trace,
+                )?;
                 if errors.is_empty() {
                     valid = true;
                     break;
                 } else {
-                    validation_errors.append(&mut errors);
+                    branch_errors.append(&mut errors);
                 }
             }
             if !valid {
+                validation_errors.append(&mut branch_errors);
                 validation_errors.push(ValidationError {
                     message: "Value does not match any of the types".to_owned(),
                     path: location.clone(),
@@ -564,6 +2898,166 @@ pub fn validate_value(
             }
             Ok(validation_errors)
         }
+        (value, Type::ExactlyOneOf(types)) => {
+            let mut matching = Vec::new();
+            let mut branch_errors = Vec::new();
+            for (i, typ) in types.iter().enumerate() {
+                let mut errors = check_value(
+                    file.clone(),
+                    location.clone(),
+                    typ,
+                    value,
+                    document,
+                    custom_types,
+                    trace,
+                )?;
+                if errors.is_empty() {
+                    matching.push(format!("#{} ({})", i + 1, typ.0.type_name()));
+                } else {
+                    branch_errors.append(&mut errors);
+                }
+            }
+            match matching.len() {
+                1 => {}
+                0 => {
+                    validation_errors.append(&mut branch_errors);
+                    validation_errors.push(ValidationError {
+                        message: "Value does not match any of the types".to_owned(),
+                        path: location.clone(),
+                        file,
+                    });
+                }
+                n => validation_errors.push(ValidationError {
+                    message: format!(
+                        "Value matches {} of the types but must match exactly one: {}",
+                        n,
+                        matching.join(", ")
+                    ),
+                    path: location.clone(),
+                    file,
+                }),
+            }
+            Ok(validation_errors)
+        }
+        (Value::String(s), Type::SameAsKey) => {
+            // location ends with the key of the enclosing object, then the property holding this value
+            let parent = location.len().checked_sub(2);
+            let in_array = parent.is_some_and(|i| {
+                matches!(node_at(document, &location[..i]), Some(Value::Array(_)))
+            });
+            match parent.map(|i| &location[i]) {
+                Some(index) if in_array => validation_errors.push(ValidationError {
+                    message: format!(
+                        "Value should be the same as its key, but the entry is stored in an array, at index {}",
+                        index
+                    ),
+                    path: location.clone(),
+                    file,
+                }),
+                Some(key) if key == s => {}
+                Some(key) => validation_errors.push(ValidationError {
+                    message: format!(
+                        "`{}` is `{}` but the entry is stored under `{}`",
+                        location.last().unwrap(),
+                        s,
+                        key
+                    ),
+                    path: location.clone(),
+                    file,
+                }),
+                None => validation_errors.push(ValidationError {
+                    message: "Value should be the same as its key, but it is not stored under any"
+                        .to_owned(),
+                    path: location.clone(),
+                    file,
+                }),
+            }
+            Ok(validation_errors)
+        }
+        (value, Type::Format(_, format)) => {
+            if let Err(message) = format.validate(value) {
+                validation_errors.push(ValidationError {
+                    message,
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (value, Type::Reference(path)) => {
+            match resolve_path(document, &location, path) {
+                None => validation_errors.push(ValidationError {
+                    message: format!(
+                        "Dangling reference: {} refers to .{}, but there is nothing there",
+                        value,
+                        path.join(".")
+                    ),
+                    path: location.clone(),
+                    file,
+                }),
+                Some((anchor, targets)) => {
+                    if !targets.iter().any(|(_, target)| *target == value) {
+                        validation_errors.push(ValidationError {
+                            message: format!(
+                                "Dangling reference: {} does not match any value at .{}",
+                                value,
+                                anchor
+                                    .iter()
+                                    .chain(path)
+                                    .cloned()
+                                    .collect::<Vec<_>>()
+                                    .join(".")
+                            ),
+                            path: location.clone(),
+                            file,
+                        });
+                    }
+                }
+            }
+            Ok(validation_errors)
+        }
+        (Value::Object(map), Type::TaggedUnion(discriminator, types)) => {
+            let tags = types
+                .keys()
+                .map(|tag| format!("`{}`", tag))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match map.get(discriminator) {
+                Some(Value::String(tag)) if types.contains_key(tag) => {
+                    validation_errors.append(&mut check_value(
+                        file,
+                        location,
+                        &types[tag],
+                        value,
+                        document,
+                        custom_types,
+                        trace,
+                    )?);
+                }
+                Some(tag) => {
+                    validation_errors.push(ValidationError {
+                        message: format!("Unknown tag {}, expected one of {}", tag, tags),
+                        path: {
+                            let mut newloc = location.clone();
+                            newloc.push(discriminator.to_string());
+                            newloc
+                        },
+                        file,
+                    });
+                }
+                None => {
+                    validation_errors.push(ValidationError {
+                        message: format!(
+                            "Object is missing property `{}`, which should be one of {}",
+                            discriminator, tags
+                        ),
+                        path: location.clone(),
+                        file,
+                    });
+                }
+            }
+            Ok(validation_errors)
+        }
         (value, Type::Enum(literals)) => {
             let mut valid = false;
             for literal in literals {
@@ -571,37 +3065,196 @@ pub fn validate_value(
                     valid = true;
                     break;
                 }
-            }
-            if !valid {
+            }
+            if !valid {
+                validation_errors.push(ValidationError {
+                    message: format!(
+                        "Value is not any of the allowed values: {}",
+                        literals
+                            .iter()
+                            .map(|l| format!("{:?}", l))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                    .to_owned(),
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (value, Type::Not(forbidden)) => {
+            if check_value(
+                file.clone(),
+                location.clone(),
+                forbidden,
+                value,
+                document,
+                custom_types,
+                trace,
+            )?
+            .is_empty()
+            {
+                validation_errors.push(ValidationError {
+                    message: format!("Value must not be {}", forbidden.0.type_name()),
+                    path: location.clone(),
+                    file,
+                });
+            }
+            Ok(validation_errors)
+        }
+        (Value::Null, Type::Nullable(_)) => Ok(Vec::new()),
+        (value, Type::Nullable(base)) => {
+            // Say "does not match type string or null" instead of "does not match type string"
+            let base_mismatch = type_mismatch(value, base);
+            Ok(check_value(
+                file,
+                location.clone(),
+                &((**base).clone(), "".to_owned()),
+                value,
+                document,
+                custom_types,
+                trace,
+            )?
+            .into_iter()
+            .map(|mut e| {
+                if e.path == location && e.message == base_mismatch {
+                    e.message = type_mismatch(value, &typ.0);
+                }
+                e
+            })
+            .collect())
+        }
+        (value, Type::Constrained(base, constraints)) => {
+            let mut base_errors = check_value(
+                file.clone(),
+                location.clone(),
+                &((**base).clone(), "".to_owned()),
+                value,
+                document,
+                custom_types,
+                trace,
+            )?;
+            // Type arguments of generic types are only known when validating: "non-empty T" with T = integer
+            if base_errors.is_empty()
+                && !value.is_string()
+                && constraints
+                    .iter()
+                    .any(|c| matches!(c, Constraint::MinLength(_) | Constraint::MaxLength(_)))
+            {
                 validation_errors.push(ValidationError {
                     message: format!(
-                        "Value is not any of the allowed values: {}",
-                        literals
-                            .into_iter()
-                            .map(|l| format!("{:?}", l))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )
-                    .to_owned(),
+                        "Length constraints only apply to strings, but value is of type {}",
+                        serde_type_name(value)
+                    ),
                     path: location.clone(),
-                    file,
+                    file: file.clone(),
                 });
             }
+            validation_errors.append(&mut base_errors);
+            for constraint in constraints {
+                if let (Constraint::UniqueItems, Value::Array(elements)) = (constraint, value) {
+                    for (i, element) in elements.iter().enumerate() {
+                        if let Some(first) = elements[..i].iter().position(|e| e == element) {
+                            validation_errors.push(ValidationError {
+                                message: format!("Item is a duplicate of item {}", first),
+                                path: {
+                                    let mut newloc = location.clone();
+                                    newloc.push(i.to_string());
+                                    newloc
+                                },
+                                file: file.clone(),
+                            });
+                        }
+                    }
+                } else if let Constraint::UniqueBy(path) = constraint {
+                    let items: Vec<(String, &Value)> = match value {
+                        Value::Array(elements) => elements
+                            .iter()
+                            .enumerate()
+                            .map(|(i, e)| (i.to_string(), e))
+                            .collect(),
+                        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+                        _ => Vec::new(),
+                    };
+                    // Every value found at path, with all the places where it was found
+                    let mut occurrences: Vec<(&Value, Vec<Vec<String>>)> = Vec::new();
+                    for (key, item) in items {
+                        let mut found = Vec::new();
+                        let mut newloc = location.clone();
+                        newloc.push(key);
+                        values_at(item, path, newloc, &mut found);
+                        for (at, found) in found {
+                            match occurrences.iter_mut().find(|(value, _)| *value == found) {
+                                Some((_, locations)) => locations.push(at),
+                                None => occurrences.push((found, vec![at])),
+                            }
+                        }
+                    }
+                    for (duplicate, locations) in occurrences {
+                        if locations.len() < 2 {
+                            continue;
+                        }
+                        for (i, at) in locations.iter().enumerate() {
+                            let others = locations
+                                .iter()
+                                .enumerate()
+                                .filter(|(j, _)| *j != i)
+                                .map(|(_, other)| format!(".{}", other.join(".")))
+                                .collect::<Vec<_>>();
+                            validation_errors.push(ValidationError {
+                                message: format!(
+                                    "Duplicate {} {}, also found at {}",
+                                    path.join("."),
+                                    duplicate,
+                                    others.join(", ")
+                                ),
+                                path: at.clone(),
+                                file: file.clone(),
+                            });
+                        }
+                    }
+                } else if let Some(message) = check_constraint(constraint, value)
+                    .or_else(|| check_temporal_constraint(constraint, value, document, &location))
+                {
+                    validation_errors.push(ValidationError {
+                        message,
+                        path: location.clone(),
+                        file: file.clone(),
+                    });
+                }
+            }
             Ok(validation_errors)
         }
-        (value, Type::Custom(type_name, spec)) => {
-            println!(
-                "Validating value of custom type {} with spec {:#?}",
-                type_name, spec
-            );
-            let validation_sub_errors =
-                validate_value(file.clone(), location.clone(), spec, value, custom_types)?;
+        (value, Type::Custom(name, arguments)) => {
+            let spec = resolve_custom(name, arguments, custom_types)?;
+            let type_name = typ.0.type_name();
+            if trace {
+                println!(
+                    "Validating value of custom type {} with spec {:#?}",
+                    type_name, spec
+                );
+            }
+            let validation_sub_errors = check_value(
+                file.clone(),
+                location.clone(),
+                &spec,
+                value,
+                document,
+                custom_types,
+                trace,
+            )?;
             if !validation_sub_errors.is_empty() {
                 validation_errors.append(
                     &mut validation_sub_errors
                         .into_iter()
                         .map(|e| ValidationError {
-                            message: format!("Custom type `{}`: {}", type_name, e.message),
+                            // Recursive types would otherwise stack one prefix per level of nesting
+                            message: if e.message.starts_with("Custom type `") {
+                                e.message
+                            } else {
+                                format!("Custom type `{}`: {}", type_name, e.message)
+                            },
                             path: e.path,
                             file: e.file,
                         })
@@ -612,12 +3265,7 @@ pub fn validate_value(
         }
         _ => {
             validation_errors.push(ValidationError {
-                message: format!(
-                    "Value has type {}, which does not match type {}",
-                    serde_type_name(value),
-                    typ.0.type_name()
-                )
-                .to_owned(),
+                message: type_mismatch(value, &typ.0),
                 path: location.clone(),
                 file,
             });
@@ -630,16 +3278,123 @@ pub fn validate_one(
     schema: &mut Schema,
     input_file: PathBuf,
 ) -> Result<Vec<ValidationError>, Box<dyn std::error::Error>> {
+    let document = serde_json::from_reader(File::open(input_file.clone())?)?;
     let validation_errors = validate_value(
-        input_file.clone(),
+        input_file,
         Vec::new(),
         &schema.value,
-        &serde_json::from_reader(File::open(input_file)?)?,
+        &document,
         &mut schema.types,
     )?;
     Ok(validation_errors)
 }
 
+/// Fills in, in place, the properties that are missing from value but have a default value in typ.
+fn fill_defaults(
+    typ: &Type,
+    value: &mut Value,
+    custom_types: &mut Typeshed,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match (typ, value) {
+        (Type::Object(object), Value::Object(map)) => {
+            for (key, default) in &object.defaults {
+                if !map.contains_key(key) {
+                    map.insert(key.clone(), default.clone());
+                }
+            }
+            for (key, value) in map.iter_mut() {
+                if let Some(property) = object.properties.get(key) {
+                    fill_defaults(&property.0, value, custom_types)?;
+                    continue;
+                }
+                for generic_key in &object.generic_keys {
+                    let key_errors = check_value(
+                        PathBuf::new(),
+                        Vec::new(),
+                        &generic_key.key,
+                        &Value::String(key.to_string()),
+                        value,
+                        custom_types,
+                        false,
+                    )?;
+                    if key_errors.is_empty() {
+                        fill_defaults(&generic_key.value.0, value, custom_types)?;
+                        break;
+                    }
+                }
+            }
+        }
+        (Type::Array(elements_type), Value::Array(elements)) => {
+            for element in elements {
+                fill_defaults(&elements_type.0, element, custom_types)?;
+            }
+        }
+        (Type::FixedSizeArray(types), Value::Array(elements)) => {
+            for (typ, element) in types.iter().zip(elements) {
+                fill_defaults(&typ.0, element, custom_types)?;
+            }
+        }
+        (Type::AllOf(types), value) => {
+            for typ in types {
+                fill_defaults(&typ.0, value, custom_types)?;
+            }
+        }
+        // Defaults come from the first branch that matches, as in validate_value
+        (Type::AnyOf(types) | Type::ExactlyOneOf(types), value) => {
+            for typ in types {
+                if check_value(
+                    PathBuf::new(),
+                    Vec::new(),
+                    typ,
+                    value,
+                    value,
+                    custom_types,
+                    false,
+                )?
+                .is_empty()
+                {
+                    return fill_defaults(&typ.0, value, custom_types);
+                }
+            }
+        }
+        (Type::TaggedUnion(discriminator, types), value) => {
+            if let Some(typ) = value
+                .get(discriminator)
+                .and_then(|tag| tag.as_str())
+                .and_then(|tag| types.get(tag))
+            {
+                fill_defaults(&typ.0, value, custom_types)?;
+            }
+        }
+        (Type::Constrained(base, _) | Type::Nullable(base), value) => {
+            fill_defaults(base, value, custom_types)?
+        }
+        (Type::Custom(name, arguments), value) => {
+            let spec = resolve_custom(name, arguments, custom_types)?;
+            fill_defaults(&spec.0, value, custom_types)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Validates input_file, and returns its content with missing default values filled in alongside the validation errors.
+pub fn validate_and_apply_defaults(
+    schema: &mut Schema,
+    input_file: PathBuf,
+) -> Result<(Value, Vec<ValidationError>), Box<dyn std::error::Error>> {
+    let mut value: Value = serde_json::from_reader(File::open(&input_file)?)?;
+    let validation_errors = validate_value(
+        input_file,
+        Vec::new(),
+        &schema.value,
+        &value,
+        &mut schema.types,
+    )?;
+    fill_defaults(&schema.value.0, &mut value, &mut schema.types)?;
+    Ok((value, validation_errors))
+}
+
 pub fn validate(
     schema: &mut Schema,
     input_files: Vec<PathBuf>,
@@ -651,23 +3406,64 @@ pub fn validate(
     Ok(validation_errors)
 }
 
+/// Collects usages of generic types in typ, keyed by their full name such as "translated<html>".
+fn collect_instantiations(typ: &Type, instantiations: &mut IndexMap<String, (String, Vec<Type>)>) {
+    if let Type::Custom(name, arguments) = typ {
+        if !arguments.is_empty() {
+            instantiations
+                .entry(typ.type_name())
+                .or_insert_with(|| (name.clone(), arguments.clone()));
+        }
+    }
+    for subtype in subtypes(typ) {
+        collect_instantiations(subtype, instantiations);
+    }
+}
+
 pub fn to_jsonschema(schema: &Schema) -> serde_json::Value {
     let mut jsonschema = type_to_jsonschema(&schema.value);
     let mut definitions = serde_json::Map::new();
+    // Generic types are monomorphised: each of their usages gets its own definition, such as "translated<html>"
+    let mut instantiations = IndexMap::new();
+    collect_instantiations(&schema.value.0, &mut instantiations);
     for (name, typ) in schema.types.iter() {
+        if let Type::Generic(_, _) | Type::Parameter(_) = typ.0 {
+            continue;
+        }
         definitions.insert(
             name.clone(),
             serde_json::Value::Object(type_to_jsonschema(typ)),
         );
+        collect_instantiations(&typ.0, &mut instantiations);
+    }
+    let mut i = 0;
+    while let Some((type_name, (name, arguments))) = instantiations.get_index(i) {
+        if let Ok(spec) = resolve_custom(name, arguments, &schema.types) {
+            definitions.insert(
+                type_name.clone(),
+                serde_json::Value::Object(type_to_jsonschema(&spec)),
+            );
+            collect_instantiations(&spec.0, &mut instantiations);
+        }
+        i += 1;
     }
     jsonschema.insert("$defs".to_string(), serde_json::Value::Object(definitions));
-    return serde_json::Value::Object(jsonschema);
+    serde_json::Value::Object(jsonschema)
+}
+
+/// Integral bounds are written as integers, so that "between 1 and 10" does not become 1.0 and 10.0
+fn json_number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
 }
 
 pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serde_json::Value> {
     let mut out = serde_json::Map::new();
 
-    if value.1 != "" {
+    if !value.1.is_empty() {
         out.insert("description".to_string(), Value::String(value.1.clone()));
     }
 
@@ -677,8 +3473,8 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
                 "allOf".to_string(),
                 serde_json::Value::Array(
                     types
-                        .into_iter()
-                        .map(|t| serde_json::Value::Object(type_to_jsonschema(&t)))
+                        .iter()
+                        .map(|t| serde_json::Value::Object(type_to_jsonschema(t)))
                         .collect(),
                 ),
             );
@@ -708,7 +3504,7 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
             );
             out.insert(
                 "items".to_string(),
-                serde_json::Value::Object(type_to_jsonschema(&typ)),
+                serde_json::Value::Object(type_to_jsonschema(typ)),
             );
         }
         Type::Boolean => {
@@ -724,12 +3520,114 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
             );
             warn("Color type is not supported in JSON Schema, yet.");
         }
-        Type::Custom(typename, _) => {
+        Type::HexColor => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+            out.insert(
+                "pattern".to_string(),
+                serde_json::Value::String("^#([0-9a-fA-F]{3}|[0-9a-fA-F]{6})$".to_string()),
+            );
+        }
+        Type::NamedColor => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+            out.insert(
+                "pattern".to_string(),
+                serde_json::Value::String("^[a-zA-Z]+$".to_string()),
+            );
+        }
+        Type::OpaqueColor => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+            warn("Opaque color type is not supported in JSON Schema, yet.");
+        }
+        Type::Not(forbidden) => {
+            out.insert(
+                "not".to_string(),
+                Value::Object(type_to_jsonschema(forbidden)),
+            );
+        }
+        Type::Nullable(base) => {
+            let base = type_to_jsonschema(&((**base).clone(), "".to_owned()));
+            match base.get("type") {
+                Some(Value::String(typ)) if typ != "any" => {
+                    let typ = typ.clone();
+                    out.extend(base);
+                    out.insert("type".to_string(), json!([typ, "null"]));
+                }
+                Some(_) => out.extend(base),
+                None => {
+                    out.insert(
+                        "anyOf".to_string(),
+                        json!([Value::Object(base), { "type": "null" }]),
+                    );
+                }
+            }
+        }
+        Type::Constrained(base, constraints) => {
+            out.extend(type_to_jsonschema(&((**base).clone(), "".to_owned())));
+            for constraint in constraints {
+                let (keyword, bound) = match *constraint {
+                    Constraint::Minimum(n) => ("minimum", json_number(n)),
+                    Constraint::ExclusiveMinimum(n) => ("exclusiveMinimum", json_number(n)),
+                    Constraint::Maximum(n) => ("maximum", json_number(n)),
+                    Constraint::ExclusiveMaximum(n) => ("exclusiveMaximum", json_number(n)),
+                    Constraint::MultipleOf(n) => ("multipleOf", json_number(n)),
+                    Constraint::MinLength(n) => ("minLength", Value::from(n)),
+                    Constraint::MaxLength(n) => ("maxLength", Value::from(n)),
+                    Constraint::MinItems(n) => ("minItems", Value::from(n)),
+                    Constraint::MaxItems(n) => ("maxItems", Value::from(n)),
+                    Constraint::UniqueItems => ("uniqueItems", Value::Bool(true)),
+                    Constraint::UniqueBy(_) => {
+                        warn("Uniqueness by a field is not convertible to json schema");
+                        continue;
+                    }
+                    Constraint::After(_)
+                    | Constraint::Before(_)
+                    | Constraint::NotBefore(_)
+                    | Constraint::NotAfter(_) => {
+                        warn("Date bounds are not convertible to json schema");
+                        continue;
+                    }
+                    Constraint::Schemes(ref schemes) => (
+                        "pattern",
+                        Value::String(format!("^({}):", schemes.join("|"))),
+                    ),
+                    Constraint::Host(ref host) => (
+                        "pattern",
+                        Value::String(format!(
+                            "^[a-zA-Z][a-zA-Z0-9+.-]*://([^/?#@]*@)?{}([:/?#]|$)",
+                            match host.strip_prefix("*.") {
+                                Some(domain) => format!("[^/?#@:]+\\.{}", regex::escape(domain)),
+                                None => regex::escape(host),
+                            }
+                        )),
+                    ),
+                };
+                // A schema has a single pattern, further ones are added with allOf
+                if keyword == "pattern" && out.contains_key("pattern") {
+                    if let Value::Array(patterns) = out.entry("allOf").or_insert(json!([])) {
+                        patterns.push(json!({ "pattern": bound }));
+                    }
+                    continue;
+                }
+                out.insert(keyword.to_string(), bound);
+            }
+        }
+        Type::Custom(_, _) => {
             out.insert(
                 "$ref".to_string(),
-                serde_json::Value::String(format!("#/definitions/{}", typename)),
+                serde_json::Value::String(format!("#/$defs/{}", value.0.type_name())),
             );
         }
+        // Only found in the typeshed, generic types are exported once per usage by to_jsonschema
+        Type::Generic(_, _) | Type::Parameter(_) => {}
         Type::Date => {
             out.insert(
                 "type".to_string(),
@@ -755,7 +3653,7 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
                 "enum".to_string(),
                 serde_json::Value::Array(
                     literals
-                        .into_iter()
+                        .iter()
                         .map(|l| serde_json::Value::String(l.to_string()))
                         .collect(),
                 ),
@@ -770,8 +3668,8 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
                 "items".to_string(),
                 serde_json::Value::Array(
                     types
-                        .into_iter()
-                        .map(|t| serde_json::Value::Object(type_to_jsonschema(&t)))
+                        .iter()
+                        .map(|t| serde_json::Value::Object(type_to_jsonschema(t)))
                         .collect(),
                 ),
             );
@@ -779,106 +3677,1475 @@ pub fn type_to_jsonschema(value: &CommentedType) -> serde_json::Map<String, serd
         Type::Float => {
             out.insert(
                 "type".to_string(),
-                serde_json::Value::String("number".to_string()),
+                serde_json::Value::String("number".to_string()),
+            );
+            out.insert(
+                "format".to_string(),
+                serde_json::Value::String("float".to_string()),
+            );
+        }
+        Type::Format(name, format) => {
+            out.insert("format".to_string(), Value::String(name.clone()));
+            out.extend(format.to_jsonschema());
+        }
+        Type::SameAsKey => {
+            warn("Same as key is not convertible to json schema, it will accept any string");
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+        }
+        Type::Reference(_) => {
+            warn("References are not convertible to json schema, they will accept any value");
+        }
+        Type::HTML | Type::HTMLPolicy(_) => {
+            warn("HTML is not convertible to json schema");
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+        }
+        Type::Integer => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("integer".to_string()),
+            );
+        }
+        Type::Literal(lit) => {
+            out.insert("const".to_string(), lit.clone());
+        }
+        Type::LiteralString(s) => {
+            out.insert("const".to_string(), Value::String(s.to_string()));
+        }
+        Type::Number => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("number".to_string()),
+            );
+        }
+        Type::Object(object) => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("object".to_string()),
+            );
+            let mut props = serde_json::Map::new();
+            let mut required = Vec::new();
+            for (key, value) in &object.properties {
+                let mut property = type_to_jsonschema(value);
+                if let Some(default) = object.defaults.get(key) {
+                    property.insert("default".to_string(), default.clone());
+                }
+                props.insert(key.to_string(), serde_json::Value::Object(property));
+                if !object.optional_properties.contains(key) {
+                    required.push(serde_json::Value::String(key.to_string()));
+                }
+            }
+            out.insert("properties".to_string(), serde_json::Value::Object(props));
+            if !required.is_empty() {
+                out.insert("required".to_string(), serde_json::Value::Array(required));
+            }
+            let mut dependent_required = serde_json::Map::new();
+            let mut rules = Vec::new();
+            let condition_schema = |Condition(property, value): &Condition| match value {
+                None => json!({ "required": [property] }),
+                Some(value) => json!({
+                    "properties": { property: { "const": value } },
+                    "required": [property]
+                }),
+            };
+            for rule in &object.rules {
+                match rule {
+                    Rule::Requires(Condition(property, None), required) => {
+                        if let Value::Array(dependencies) = dependent_required
+                            .entry(property.clone())
+                            .or_insert(json!([]))
+                        {
+                            dependencies.extend(required.iter().map(|r| json!(r)));
+                        }
+                    }
+                    Rule::Requires(condition, required) => rules.push(json!({
+                        "if": condition_schema(condition),
+                        "then": { "required": required }
+                    })),
+                    Rule::Forbids(condition, forbidden) => rules.push(json!({
+                        "if": condition_schema(condition),
+                        "then": {
+                            "not": {
+                                "anyOf": forbidden
+                                    .iter()
+                                    .map(|f| json!({ "required": [f] }))
+                                    .collect::<Vec<_>>()
+                            }
+                        }
+                    })),
+                    Rule::ExactlyOneOf(properties) => rules.push(json!({
+                        "oneOf": properties
+                            .iter()
+                            .map(|p| json!({ "required": [p] }))
+                            .collect::<Vec<_>>()
+                    })),
+                    Rule::Contrast(_, _, _) => {
+                        warn("Contrast rules are not convertible to json schema")
+                    }
+                }
+            }
+            if !dependent_required.is_empty() {
+                out.insert(
+                    "dependentRequired".to_string(),
+                    Value::Object(dependent_required),
+                );
+            }
+            if !rules.is_empty() {
+                out.insert("allOf".to_string(), Value::Array(rules));
+            }
+            if object.additional_properties {
+                out.insert(
+                    "additionalProperties".to_string(),
+                    serde_json::Value::Bool(true),
+                );
+            } else if !object.generic_keys.is_empty() {
+                let any_of = |mut schemas: Vec<Value>| {
+                    if schemas.len() == 1 {
+                        schemas.remove(0)
+                    } else {
+                        let mut any_of = serde_json::Map::new();
+                        any_of.insert("anyOf".to_string(), Value::Array(schemas));
+                        Value::Object(any_of)
+                    }
+                };
+                let mut key_schemas = Vec::new();
+                // Explicitly named properties must be allowed as property names too
+                if !object.properties.is_empty() {
+                    let mut explicit_keys = serde_json::Map::new();
+                    explicit_keys.insert(
+                        "enum".to_string(),
+                        Value::Array(
+                            object
+                                .properties
+                                .keys()
+                                .map(|k| Value::String(k.to_string()))
+                                .collect(),
+                        ),
+                    );
+                    key_schemas.push(Value::Object(explicit_keys));
+                }
+                for generic_key in &object.generic_keys {
+                    key_schemas.push(Value::Object(type_to_jsonschema(&generic_key.key)));
+                }
+                out.insert("propertyNames".to_string(), any_of(key_schemas));
+                out.insert(
+                    "additionalProperties".to_string(),
+                    any_of(
+                        object
+                            .generic_keys
+                            .iter()
+                            .map(|k| Value::Object(type_to_jsonschema(&k.value)))
+                            .collect(),
+                    ),
+                );
+                if let [generic_key] = object.generic_keys.as_slice() {
+                    let describe = |typ: &CommentedType| {
+                        if typ.1.is_empty() {
+                            typ.0.type_name()
+                        } else {
+                            typ.1.clone()
+                        }
+                    };
+                    out.entry("description").or_insert(Value::String(format!(
+                        "Maps from {} to {}",
+                        describe(&generic_key.key),
+                        describe(&generic_key.value)
+                    )));
+                }
+            }
+        }
+        Type::AnyOf(types) => {
+            out.insert(
+                "anyOf".to_string(),
+                serde_json::Value::Array(
+                    types
+                        .iter()
+                        .map(|t| serde_json::Value::Object(type_to_jsonschema(t)))
+                        .collect(),
+                ),
+            );
+        }
+        Type::ExactlyOneOf(types) => {
+            out.insert(
+                "oneOf".to_string(),
+                serde_json::Value::Array(
+                    types
+                        .iter()
+                        .map(|t| serde_json::Value::Object(type_to_jsonschema(t)))
+                        .collect(),
+                ),
+            );
+        }
+        Type::TaggedUnion(discriminator, types) => {
+            let mut branches = Vec::new();
+            for (tag, typ) in types {
+                let branch = type_to_jsonschema(typ);
+                // Inline objects already hold their tag as a constant property
+                if let Type::Object(_) = typ.0 {
+                    branches.push(Value::Object(branch));
+                    continue;
+                }
+                let mut tag_schema = serde_json::Map::new();
+                tag_schema.insert("const".to_string(), Value::String(tag.clone()));
+                let mut properties = serde_json::Map::new();
+                properties.insert(discriminator.clone(), Value::Object(tag_schema));
+                let mut discriminated = serde_json::Map::new();
+                discriminated.insert("properties".to_string(), Value::Object(properties));
+                discriminated.insert(
+                    "required".to_string(),
+                    Value::Array(vec![Value::String(discriminator.clone())]),
+                );
+                let mut all_of = serde_json::Map::new();
+                all_of.insert(
+                    "allOf".to_string(),
+                    Value::Array(vec![Value::Object(branch), Value::Object(discriminated)]),
+                );
+                branches.push(Value::Object(all_of));
+            }
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("object".to_string()),
+            );
+            out.insert("oneOf".to_string(), Value::Array(branches));
+        }
+        Type::RegexPattern(pat) => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+            out.insert(
+                "pattern".to_string(),
+                serde_json::Value::String(pat.to_string()),
+            );
+        }
+        Type::String => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+        }
+        Type::Time => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
+            );
+            out.insert(
+                "format".to_string(),
+                serde_json::Value::String("time".to_string()),
+            );
+        }
+        Type::URL => {
+            out.insert(
+                "type".to_string(),
+                serde_json::Value::String("string".to_string()),
             );
             out.insert(
                 "format".to_string(),
-                serde_json::Value::String("float".to_string()),
+                serde_json::Value::String("uri".to_string()),
             );
         }
-        Type::HTML => {
-            warn("HTML is not convertible to json schema");
+        Type::RelativeURL | Type::URLOrPath => {
             out.insert(
                 "type".to_string(),
                 serde_json::Value::String("string".to_string()),
             );
-        }
-        Type::Integer => {
             out.insert(
-                "type".to_string(),
-                serde_json::Value::String("integer".to_string()),
+                "format".to_string(),
+                serde_json::Value::String("uri-reference".to_string()),
             );
+            if let Type::RelativeURL = value.0 {
+                out.insert(
+                    "not".to_string(),
+                    json!({ "pattern": "^[a-zA-Z][a-zA-Z0-9+.-]*:" }),
+                );
+            }
         }
-        Type::Literal(lit) => {
-            out.insert("const".to_string(), lit.clone());
-        }
-        Type::LiteralString(s) => {
-            out.insert("const".to_string(), Value::String(s.to_string()));
-        }
-        Type::Number => {
+        Type::Email | Type::UUID | Type::IPv4 | Type::IPv6 | Type::Hostname => {
             out.insert(
                 "type".to_string(),
-                serde_json::Value::String("number".to_string()),
+                serde_json::Value::String("string".to_string()),
+            );
+            out.insert(
+                "format".to_string(),
+                serde_json::Value::String(value.0.type_name()),
             );
         }
-        Type::Object(obj, additional_props) => {
+        Type::IP => {
             out.insert(
                 "type".to_string(),
-                serde_json::Value::String("object".to_string()),
+                serde_json::Value::String("string".to_string()),
             );
-            let mut props = serde_json::Map::new();
-            for (key, value) in obj {
-                props.insert(
-                    key.to_string(),
-                    serde_json::Value::Object(type_to_jsonschema(value)),
-                );
-            }
-            out.insert("properties".to_string(), serde_json::Value::Object(props));
-            if *additional_props {
-                out.insert(
-                    "additionalProperties".to_string(),
-                    serde_json::Value::Bool(true),
-                );
-            }
-        }
-        Type::OneOf(types) => {
             out.insert(
-                "oneOf".to_string(),
-                serde_json::Value::Array(
-                    types
-                        .into_iter()
-                        .map(|t| serde_json::Value::Object(type_to_jsonschema(&t)))
-                        .collect(),
-                ),
+                "anyOf".to_string(),
+                json!([{ "format": "ipv4" }, { "format": "ipv6" }]),
             );
         }
-        Type::RegexPattern(pat) => {
+        Type::CIDR => {
+            // JSON Schema has no format for network ranges
             out.insert(
                 "type".to_string(),
                 serde_json::Value::String("string".to_string()),
             );
             out.insert(
                 "pattern".to_string(),
-                serde_json::Value::String(pat.to_string()),
+                serde_json::Value::String(r"^[0-9A-Fa-f:.]+/[0-9]{1,3}$".to_string()),
             );
         }
-        Type::String => {
+        Type::Duration => {
             out.insert(
                 "type".to_string(),
                 serde_json::Value::String("string".to_string()),
             );
+            out.insert(
+                "format".to_string(),
+                serde_json::Value::String("duration".to_string()),
+            );
         }
-        Type::Time => {
+        Type::Timezone => {
             out.insert(
                 "type".to_string(),
                 serde_json::Value::String("string".to_string()),
             );
             out.insert(
-                "format".to_string(),
-                serde_json::Value::String("time".to_string()),
+                "pattern".to_string(),
+                serde_json::Value::String(
+                    r"^([A-Za-z_]+(/[A-Za-z0-9_+-]+)*|Z|[+-][0-9]{2}(:?[0-9]{2})?)$".to_string(),
+                ),
             );
         }
-        Type::URL => {
+        // JSON Schema has no format for these, so they are approximated with patterns
+        Type::SemVer | Type::SemVerRange | Type::Slug | Type::LanguageTag | Type::MIMEType => {
+            let version = r"(0|[1-9]\d*)\.(0|[1-9]\d*)\.(0|[1-9]\d*)(-[0-9A-Za-z-]+(\.[0-9A-Za-z-]+)*)?(\+[0-9A-Za-z-]+(\.[0-9A-Za-z-]+)*)?";
+            let comparator =
+                r"(\*|[\^~=]|[<>]=?)?\s*(\d+|[xX*])(\.(\d+|[xX*])){0,2}(-[0-9A-Za-z.-]+)?";
+            let pattern = match value.0 {
+                Type::SemVer => format!("^{}$", version),
+                Type::SemVerRange => format!(r"^\s*{0}(\s*,\s*{0})*\s*$", comparator),
+                Type::Slug => r"^[a-z0-9]+(-[a-z0-9]+)*$".to_owned(),
+                Type::LanguageTag => r"^([A-Za-z]{2,8}|[xXiI])(-[A-Za-z0-9]{1,8})*$".to_owned(),
+                _ => r#"^[\w.+-]+/[\w.+-]+(\s*;\s*[\w.+-]+=("[^"]*"|[\w.+-]+))*$"#.to_owned(),
+            };
             out.insert(
                 "type".to_string(),
                 serde_json::Value::String("string".to_string()),
             );
-            out.insert(
-                "format".to_string(),
-                serde_json::Value::String("uri".to_string()),
+            out.insert("pattern".to_string(), serde_json::Value::String(pattern));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn load_inline(value: Value) -> Result<Schema, Box<dyn std::error::Error>> {
+        let mut types = Typeshed::new();
+        let value = load_type(value, &mut types)?;
+        check_loaded_schema(&value.0, &types)?;
+        Ok(Schema { types, value })
+    }
+
+    /// Messages of the errors reported when validating document against schema.
+    fn errors(schema: &mut Schema, document: Value) -> Vec<String> {
+        validate_value(
+            PathBuf::new(),
+            Vec::new(),
+            &schema.value,
+            &document,
+            &mut schema.types,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|error| format!(".{}: {}", error.path.join("."), error.message))
+        .collect()
+    }
+
+    #[test]
+    fn rejects_length_constraints_on_custom_non_strings() {
+        for schema in [
+            json!({"(types)": {"n": "integer"}, "a": "n of length 2"}),
+            json!({"(types)": {"n": "integer"}, "a": "non-empty n"}),
+            json!({"(types)": {"name": "n of length 2", "n": "integer"}, "a": "name"}),
+        ] {
+            assert!(load_inline(schema.clone()).is_err(), "{}", schema);
+        }
+        let mut schema = load_inline(json!({
+            "(types)": {"n": "string", "name<T>": "non-empty T"},
+            "a": "n of length 2",
+            "b": "name<integer>"
+        }))
+        .unwrap();
+        assert_eq!(
+            errors(&mut schema, json!({"a": "abc", "b": 12345})),
+            [
+                ".a: String has 3 characters, more than the maximum 2",
+                ".b: Custom type `name<integer>`: Length constraints only apply to strings, but value is of type number"
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_defaults_that_depend_on_the_document() {
+        assert!(load_inline(json!({
+            "(types)": {"id": "same as key"},
+            "names": ["string"],
+            "people": {"(string)": {"id": "same as key = x", "alias": "id = x", "name": "reference to .names.* = a"}}
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn substitutes_type_arguments() {
+        let schema = load_inline(json!({
+            "(types)": {"translated<T>": {"fr": "T", "en?": "T"}},
+            "title": "translated<string>"
+        }))
+        .unwrap();
+        let (Type::Object(object), _) =
+            resolve_custom("translated", &[Type::String], &schema.types).unwrap()
+        else {
+            panic!("translated<string> should resolve to an object");
+        };
+        assert!(matches!(object.properties["fr"].0, Type::String));
+        assert!(matches!(object.properties["en"].0, Type::String));
+        assert!(resolve_custom("translated", &[], &schema.types).is_err());
+        assert!(
+            resolve_custom("translated", &[Type::String, Type::Integer], &schema.types).is_err()
+        );
+    }
+
+    #[test]
+    fn monomorphises_generic_types() {
+        let schema = load_inline(json!({
+            "(types)": {
+                "translated<T>": {"fr": "T", "en": "T"},
+                "tree<T>": {"value": "T", "children": ["tree<T>"]}
+            },
+            "title": "translated<string>",
+            "nested": "translated<translated<integer>>",
+            "tree": "tree<integer>"
+        }))
+        .unwrap();
+        let jsonschema = to_jsonschema(&schema);
+        let definitions = jsonschema["$defs"].as_object().unwrap();
+        let mut names = definitions.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "translated<integer>",
+                "translated<string>",
+                "translated<translated<integer>>",
+                "tree<integer>"
+            ]
+        );
+        assert_eq!(
+            definitions["translated<translated<integer>>"]["properties"]["fr"]["$ref"],
+            "#/$defs/translated<integer>"
+        );
+        assert_eq!(
+            definitions["tree<integer>"]["properties"]["children"]["items"]["$ref"],
+            "#/$defs/tree<integer>"
+        );
+    }
+
+    #[test]
+    fn splits_documentation_after_type_arguments() {
+        assert_eq!(
+            split_documentation("pair<string, translated<integer>>, a pair"),
+            ("pair<string, translated<integer>>", Some("a pair"))
+        );
+        assert_eq!(
+            split_documentation("matches regex ^<, the doc"),
+            ("matches regex ^<", Some("the doc"))
+        );
+        assert_eq!(
+            split_documentation("not literally a<b, the doc"),
+            ("not literally a<b", Some("the doc"))
+        );
+        assert_eq!(
+            split_documentation("'<', the doc"),
+            ("'<'", Some("the doc"))
+        );
+    }
+
+    #[test]
+    fn applies_modifiers_to_generic_types() {
+        let schema = load_inline(json!({
+            "(types)": {"name<T>": "T"},
+            "title": "non-empty name<string>",
+            "subtitle": "maybe name<string>"
+        }))
+        .unwrap();
+        let Type::Object(object) = &schema.value.0 else {
+            panic!("schema should be an object");
+        };
+        assert!(matches!(
+            &object.properties["title"].0,
+            Type::Constrained(base, constraints)
+                if matches!(**base, Type::Custom(_, _)) && matches!(constraints[..], [Constraint::MinLength(1)])
+        ));
+        assert!(matches!(object.properties["subtitle"].0, Type::Nullable(_)));
+    }
+
+    #[test]
+    fn loads_the_meta_schema() {
+        let schema =
+            load(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("strudach.strudach.yaml")).unwrap();
+        assert!(matches!(schema.value.0, Type::AnyOf(_)));
+        assert!(schema.types.contains_key("typeshed"));
+    }
+
+    #[test]
+    fn rejects_expanding_generic_recursion() {
+        assert!(load_inline(json!({
+            "(types)": {"t<T>": {"v": "T", "next?": "t<t<T>>"}},
+            "x": "t<string>"
+        }))
+        .is_err());
+        assert!(load_inline(json!({
+            "(types)": {
+                "f<T>": {"g": "g<list<T>>"},
+                "g<T>": {"f?": "f<T>"},
+                "list<T>": ["T"]
+            },
+            "x": "f<string>"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn validates_optional_properties() {
+        let mut schema = load_inline(json!({"title": "string", "footnotes?": ["string"]})).unwrap();
+        assert!(errors(&mut schema, json!({"title": "a"})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"footnotes": [1]})),
+            [
+                ".footnotes.0: Value has type number, which does not match type string",
+                ".: Object is missing property `title`"
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_recursive_and_forward_references() {
+        let mut schema = load_inline(json!({
+            "(types)": {
+                "tree": {"value": "id", "children": ["tree"]},
+                "id": "integer"
+            },
+            "root": "tree"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"root": {"value": 1, "children": [{"value": 2, "children": []}]}})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"root": {"value": 1, "children": [{"value": "x", "children": []}]}})
+            ),
+            [".root.children.0.value: Custom type `id`: Value has type string, which does not match type integer"]
+        );
+        assert!(load_inline(json!({"(types)": {"a": "b", "b": "a"}, "x": "a"})).is_err());
+    }
+
+    #[test]
+    fn parses_numeric_constraints() {
+        assert!(matches!(
+            parse_numeric_constraints("between 1 and 10").unwrap()[..],
+            [Constraint::Minimum(min), Constraint::Maximum(max)] if min == 1.0 && max == 10.0
+        ));
+        assert!(matches!(
+            parse_numeric_constraints("above -1.5 and multiple of 2").unwrap()[..],
+            [Constraint::ExclusiveMinimum(min), Constraint::MultipleOf(divisor)] if min == -1.5 && divisor == 2.0
+        ));
+        assert!(matches!(
+            parse_numeric_constraints("at least 5 and at most 5").unwrap()[..],
+            [Constraint::Minimum(_), Constraint::Maximum(_)]
+        ));
+        for constraints in [
+            "",
+            "above",
+            "at least x",
+            "multiple of 0",
+            "and at least 1",
+            "between 10 and 1",
+            "at least 5 and below 5",
+            "above 1 and at most 0",
+        ] {
+            assert!(
+                parse_numeric_constraints(constraints).is_err(),
+                "{}",
+                constraints
+            );
+        }
+    }
+
+    #[test]
+    fn validates_numeric_constraints() {
+        let mut schema = load_inline(json!({
+            "n": "integer between 1 and 10 and multiple of 2",
+            "x": "number above 0"
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"n": 4, "x": 0.5})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"n": 11, "x": 0})),
+            [
+                ".n: Number 11 is greater than the maximum 10",
+                ".n: Number 11 is not a multiple of 2",
+                ".x: Number 0 is not above 0"
+            ]
+        );
+        let mut schema = load_inline(
+            json!({"(types)": {"integer id": "integer at least 1"}, "id": "integer id"}),
+        )
+        .unwrap();
+        assert_eq!(
+            errors(&mut schema, json!({"id": 0})),
+            [".id: Custom type `integer id`: Number 0 is less than the minimum 1"]
+        );
+    }
+
+    #[test]
+    fn parses_length_constraints() {
+        assert!(matches!(
+            parse_length_constraints("length 2").unwrap()[..],
+            [Constraint::MinLength(2), Constraint::MaxLength(2)]
+        ));
+        assert!(matches!(
+            parse_length_constraints("at most 1 character").unwrap()[..],
+            [Constraint::MaxLength(1)]
+        ));
+        assert!(matches!(
+            parse_length_constraints("between 1 and 3 characters").unwrap()[..],
+            [Constraint::MinLength(1), Constraint::MaxLength(3)]
+        ));
+        for constraints in [
+            "at most 80",
+            "length -1",
+            "length 2.5",
+            "at least many characters",
+        ] {
+            assert!(
+                parse_length_constraints(constraints).is_err(),
+                "{}",
+                constraints
+            );
+        }
+    }
+
+    #[test]
+    fn validates_length_constraints() {
+        let mut schema = load_inline(json!({
+            "s": "string of at most 3 characters",
+            "t": "non-empty string"
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"s": "abc", "t": "a"})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"s": "abcd", "t": ""})),
+            [
+                ".s: String has 4 characters, more than the maximum 3",
+                ".t: String is empty"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_items_constraints() {
+        assert!(matches!(
+            parse_items_constraints("at least 1").unwrap()[..],
+            [Constraint::MinItems(1)]
+        ));
+        assert!(matches!(
+            parse_items_constraints("above 1 and below 4").unwrap()[..],
+            [Constraint::MinItems(2), Constraint::MaxItems(3)]
+        ));
+        for constraints in ["below 0", "at least 1.5", "at least -1", "multiple of 2"] {
+            assert!(
+                parse_items_constraints(constraints).is_err(),
+                "{}",
+                constraints
+            );
+        }
+    }
+
+    #[test]
+    fn validates_items_constraints() {
+        let mut schema = load_inline(json!({
+            "a": {"(array of)": "integer", "(items)": "between 1 and 2", "(unique)": true}
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"a": [1, 2]})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"a": [1, 1, 1]})),
+            [
+                ".a: Array has 3 items, more than the maximum 2",
+                ".a.1: Item is a duplicate of item 0",
+                ".a.2: Item is a duplicate of item 0"
+            ]
+        );
+        assert_eq!(
+            errors(&mut schema, json!({"a": []})),
+            [".a: Array has 0 items, fewer than the minimum 1"]
+        );
+    }
+
+    #[test]
+    fn tries_generic_keys_in_declaration_order() {
+        let mut schema = load_inline(json!({
+            "(literally x)": "integer",
+            "(matches regex ^x)": "string",
+            "(string)": "boolean"
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"x": 1, "xy": "a", "z": true})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"x": "a", "w": 3})),
+            [
+                ".x: Value has type string, which does not match type integer",
+                ".w: Value has type number, which does not match type boolean"
+            ]
+        );
+        let mut schema =
+            load_inline(json!({"(string)": "boolean", "(literally x)": "integer"})).unwrap();
+        assert_eq!(
+            errors(&mut schema, json!({"x": 1})),
+            [".x: Value has type number, which does not match type boolean"]
+        );
+    }
+
+    #[test]
+    fn splits_documentation() {
+        assert_eq!(split_documentation("string"), ("string", None));
+        assert_eq!(
+            split_documentation("string, the title, in English"),
+            ("string", Some("the title, in English"))
+        );
+        assert_eq!(
+            split_documentation("pair<string, integer>, a pair"),
+            ("pair<string, integer>", Some("a pair"))
+        );
+    }
+
+    #[test]
+    fn documents_generic_keys() {
+        let mut schema =
+            load_inline(json!({"ages": {"(string, the name)": "integer, the age"}})).unwrap();
+        let Type::Object(object) = &schema.value.0 else {
+            panic!("schema should be an object");
+        };
+        let Type::Object(ages) = &object.properties["ages"].0 else {
+            panic!("ages should be an object");
+        };
+        assert_eq!(ages.generic_keys[0].key.1, "the name");
+        assert_eq!(ages.generic_keys[0].value.1, "the age");
+        let jsonschema = to_jsonschema(&schema);
+        assert_eq!(
+            jsonschema["properties"]["ages"]["propertyNames"]["description"],
+            "the name"
+        );
+        assert_eq!(
+            jsonschema["properties"]["ages"]["additionalProperties"]["description"],
+            "the age"
+        );
+        assert_eq!(
+            errors(&mut schema, json!({"ages": {"a": "b"}})),
+            [".ages.a: Value has type string, which does not match type integer"]
+        );
+    }
+
+    #[test]
+    fn splits_defaults() {
+        assert_eq!(
+            split_default(json!("integer = 3, the count")),
+            (json!("integer, the count"), Some(json!(3)))
+        );
+        assert_eq!(
+            split_default(json!("string = untitled")),
+            (json!("string"), Some(json!("untitled")))
+        );
+        assert_eq!(
+            split_default(json!("maybe integer = null")),
+            (json!("maybe integer"), Some(json!(null)))
+        );
+        for typestring in [
+            "literally x = y",
+            "'x = y'",
+            "matches regex ^a = b$",
+            "not literally x = y",
+            "maybe literally a = b",
+            "not maybe just a = b",
+            "integer",
+        ] {
+            assert_eq!(
+                split_default(json!(typestring)),
+                (json!(typestring), None),
+                "{}",
+                typestring
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_defaults() {
+        assert!(load_inline(json!({"n": "integer = 3"})).is_ok());
+        assert!(load_inline(json!({"n": "integer = hello"})).is_err());
+        assert!(load_inline(json!({"n": "integer at most 2 = 3"})).is_err());
+    }
+
+    /// Writes content to a file of a temporary directory, for the tests that need actual files.
+    fn write_file(name: &str, content: &Value) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("strudach-tests-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        fs::write(&path, content.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn applies_defaults() {
+        let mut schema = load(write_file(
+            "defaults.strudach.json",
+            &json!({"title": "string", "wip": "boolean = false", "tags": {"n": "integer = 3"}}),
+        ))
+        .unwrap();
+        let (value, errors) = validate_and_apply_defaults(
+            &mut schema,
+            write_file("defaults.json", &json!({"title": "a", "tags": {}})),
+        )
+        .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(value, json!({"title": "a", "tags": {"n": 3}, "wip": false}));
+    }
+
+    #[test]
+    fn reports_tagged_union_errors() {
+        let mut schema = load_inline(json!({
+            "shape": {
+                "(tagged by)": "kind",
+                "circle": {"radius": "number"},
+                "square": {"side": "number"}
+            }
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"shape": {"kind": "square", "side": 1}})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"shape": {"kind": "circle", "side": 1}})),
+            [
+                ".shape: Object has additional property `side`",
+                ".shape: Object is missing property `radius`"
+            ]
+        );
+        assert_eq!(
+            errors(&mut schema, json!({"shape": {"kind": "triangle"}})),
+            [".shape.kind: Unknown tag \"triangle\", expected one of `circle`, `square`"]
+        );
+        assert_eq!(
+            errors(&mut schema, json!({"shape": {"radius": 1}})),
+            [".shape: Object is missing property `kind`, which should be one of `circle`, `square`"]
+        );
+    }
+
+    #[test]
+    fn checks_rules_between_properties() {
+        let mut schema = load_inline(json!({
+            "started?": "date",
+            "finished?": "date",
+            "wip?": "boolean",
+            "url?": "url",
+            "file?": "string",
+            "(rules)": [
+                "finished requires started",
+                "wip: true forbids finished",
+                "exactly one of url, file"
+            ]
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"started": "2020-01-01", "finished": "2020-02-01", "wip": false, "file": "a"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"finished": "2020-01-01", "wip": true})),
+            [
+                ".: `finished` is present, so `started` is required",
+                ".: `wip` is true, so `finished` is not allowed",
+                ".: Exactly one of `url`, `file` must be set, but none is"
+            ]
+        );
+        assert!(load_inline(json!({"a?": "string", "(rules)": "a needs b"})).is_err());
+    }
+
+    #[test]
+    fn validates_nullable_types() {
+        let mut schema =
+            load_inline(json!({"a": "maybe integer", "b": "integer or null"})).unwrap();
+        assert!(errors(&mut schema, json!({"a": null, "b": 1})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"a": "x", "b": "y"})),
+            [
+                ".a: Value has type string, which does not match type integer or null",
+                ".b: Value has type string, which does not match type integer or null"
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_generic_types() {
+        let mut schema = load_inline(json!({
+            "(types)": {"translated<T>": {"fr": "T", "en": "T"}},
+            "title": "translated<string>",
+            "count": "translated<integer>"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"title": {"fr": "a", "en": "b"}, "count": {"fr": 1, "en": 2}})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"title": {"fr": 1, "en": "b"}, "count": {"fr": 1}})
+            ),
+            [
+                ".title.fr: Custom type `translated<string>`: Value has type number, which does not match type string",
+                ".count: Custom type `translated<integer>`: Object is missing property `en`"
+            ]
+        );
+    }
+
+    #[test]
+    fn imports_types_from_other_files() {
+        write_file(
+            "common.strudach.json",
+            &json!({"(types)": {"id": "integer at least 1", "translated<T>": {"fr": "T", "en": "T"}}}),
+        );
+        let mut schema = load(write_file(
+            "importing.strudach.json",
+            &json!({
+                "(import)": {"common": "common.strudach.json"},
+                "id": "common.id",
+                "title": "common.translated<string>"
+            }),
+        ))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"id": 1, "title": {"fr": "a", "en": "b"}})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"id": 0, "title": {"fr": "a", "en": 1}})),
+            [
+                ".id: Custom type `common.id`: Number 0 is less than the minimum 1",
+                ".title.en: Custom type `common.translated<string>`: Value has type number, which does not match type string"
+            ]
+        );
+        let mut schema = load(write_file(
+            "importing-unprefixed.strudach.json",
+            &json!({"(import)": "common.strudach.json", "id": "id"}),
+        ))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"id": 1})).is_empty());
+    }
+
+    #[test]
+    fn rejects_import_cycles() {
+        write_file(
+            "cycle-a.strudach.json",
+            &json!({"(import)": "cycle-b.strudach.json"}),
+        );
+        let path = write_file(
+            "cycle-b.strudach.json",
+            &json!({"(import)": "cycle-a.strudach.json"}),
+        );
+        let Err(error) = load(path) else {
+            panic!("import cycles should be rejected");
+        };
+        assert!(error.to_string().contains("cycle"), "{}", error);
+    }
+
+    #[test]
+    fn validates_negations() {
+        let mut schema =
+            load_inline(json!({"a": "not literally TODO", "b": {"(not)": "integer"}})).unwrap();
+        assert!(errors(&mut schema, json!({"a": "done", "b": "x"})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"a": "TODO", "b": 1})),
+            [
+                ".a: Value must not be literally \"TODO\"",
+                ".b: Value must not be integer"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_exactly_one_of_and_any_of_errors() {
+        let mut schema = load_inline(json!({
+            "a": {"(exactly one of)": ["integer", "number"]},
+            "b": {"(exactly one of)": ["integer", "boolean"]},
+            "c": {"(any of)": ["integer", "number"]},
+            "d": {"(any of)": ["url", "literally none"]}
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": 1.5, "b": true, "c": 1, "d": "none"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"a": 1, "b": "x", "c": 1, "d": "none"})),
+            [
+                ".a: Value matches 2 of the types but must match exactly one: #1 (integer), #2 (number)",
+                ".b: Value has type string, which does not match type integer",
+                ".b: Value has type string, which does not match type boolean",
+                ".b: Value does not match any of the types"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_references_against_the_document() {
+        let mut schema = load_inline(json!({
+            "media": {"(string)": {"id": "string"}},
+            "cover": "reference to .media.*.id"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"media": {"a": {"id": "x"}}, "cover": "x"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"media": {"a": {"id": "x"}}, "cover": "y"})
+            ),
+            [".cover: Dangling reference: \"y\" does not match any value at .media.*.id"]
+        );
+        // A value nested in a document is checked against that document
+        let Type::Object(object) = &schema.value.0 else {
+            panic!("schema should be an object");
+        };
+        let cover = object.properties["cover"].clone();
+        let document = json!({"media": {"a": {"id": "x"}}, "cover": "x"});
+        assert!(validate_value_in_document(
+            PathBuf::new(),
+            vec!["cover".to_owned()],
+            &cover,
+            &document["cover"],
+            &document,
+            &mut schema.types,
+        )
+        .unwrap()
+        .is_empty());
+        assert_eq!(
+            validate_value(
+                PathBuf::new(),
+                vec!["cover".to_owned()],
+                &cover,
+                &document["cover"],
+                &mut schema.types,
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn parses_unique_by() {
+        assert!(matches!(
+            &parse_unique_by(&json!("id")).unwrap()[..],
+            [Constraint::UniqueBy(path)] if path == &["id"]
+        ));
+        assert!(matches!(
+            &parse_unique_by(&json!([".metadata.id", "name"])).unwrap()[..],
+            [Constraint::UniqueBy(first), Constraint::UniqueBy(second)]
+                if first == &["metadata", "id"] && second == &["name"]
+        ));
+        for paths in [json!(""), json!("a..b"), json!(3), json!([])] {
+            assert!(parse_unique_by(&paths).is_err(), "{}", paths);
+        }
+    }
+
+    #[test]
+    fn validates_uniqueness_by_field() {
+        let mut schema = load_inline(json!({
+            "items": {"(array of)": {"id": "integer"}, "(unique by)": "id"}
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"items": [{"id": 1}, {"id": 2}]})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"items": [{"id": 1}, {"id": 1}]})),
+            [
+                ".items.0.id: Duplicate id 1, also found at .items.1.id",
+                ".items.1.id: Duplicate id 1, also found at .items.0.id"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_values_against_their_key() {
+        let mut schema = load_inline(json!({
+            "cats": {"(string)": {"id": "same as key"}},
+            "dogs": [{"id": "same as key"}]
+        }))
+        .unwrap();
+        assert!(errors(&mut schema, json!({"cats": {"a": {"id": "a"}}, "dogs": []})).is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"cats": {"a": {"id": "b"}}, "dogs": [{"id": "0"}]})
+            ),
+            [
+                ".cats.a.id: `id` is `b` but the entry is stored under `a`",
+                ".dogs.0.id: Value should be the same as its key, but the entry is stored in an array, at index 0"
+            ]
+        );
+    }
+
+    struct Even;
+
+    impl Format for Even {
+        fn validate(&self, value: &Value) -> Result<(), String> {
+            match value.as_i64() {
+                Some(n) if n % 2 == 0 => Ok(()),
+                _ => Err("Value is not an even integer".to_owned()),
+            }
+        }
+
+        fn to_jsonschema(&self) -> serde_json::Map<String, Value> {
+            json!({"type": "integer", "multipleOf": 2})
+                .as_object()
+                .unwrap()
+                .clone()
+        }
+    }
+
+    #[test]
+    fn validates_registered_formats() {
+        let mut formats = Formats::new();
+        formats.insert(
+            "isbn".to_owned(),
+            Rc::new(|value: &Value| match value.as_str() {
+                Some(isbn) if isbn.len() == 13 => Ok(()),
+                _ => Err("String is not a valid ISBN".to_owned()),
+            }),
+        );
+        formats.insert("even".to_owned(), Rc::new(Even));
+        let mut schema = load_with_formats(
+            write_file(
+                "formats.strudach.json",
+                &json!({"book": "isbn", "pages": "even"}),
+            ),
+            &formats,
+        )
+        .unwrap();
+        assert!(errors(&mut schema, json!({"book": "9780000000000", "pages": 2})).is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"book": "123", "pages": 3})),
+            [
+                ".book: Custom type `isbn`: String is not a valid ISBN",
+                ".pages: Custom type `even`: Value is not an even integer"
+            ]
+        );
+        assert_eq!(
+            to_jsonschema(&schema)["$defs"]["even"]["multipleOf"],
+            json!(2)
+        );
+        assert!(load(write_file(
+            "formats-unregistered.strudach.json",
+            &json!({"book": "isbn"})
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn validates_network_and_identifier_formats() {
+        let mut schema = load_inline(json!({
+            "a": "email",
+            "b": "uuid",
+            "c": "ipv4",
+            "d": "hostname"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": "a@example.com", "b": "67e55044-10b1-426f-9247-bb680e5fe0c8", "c": "1.2.3.4", "d": "example.com"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"a": "x", "b": "y", "c": "1.2.3", "d": "-x"})
+            ),
+            [
+                ".a: String is not a valid email address",
+                ".b: String is not a valid UUID: failed to parse a UUID",
+                ".c: String is not a valid IPv4 address",
+                ".d: Hostname label \"-x\" starts or ends with a hyphen"
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_versioning_formats() {
+        let mut schema = load_inline(json!({
+            "a": "semver",
+            "b": "slug",
+            "c": "language tag",
+            "d": "mime type"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": "1.2.3", "b": "a-slug", "c": "fr-CA", "d": "text/html"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(&mut schema, json!({"a": "1.2", "b": "Not A Slug", "c": "123", "d": "text"})),
+            [
+                ".a: String is not a valid semantic version: unexpected end of input while parsing minor version number",
+                ".b: Slug contains 'N', only lowercase letters, digits and hyphens are allowed",
+                ".c: String is not a well-formed language tag: the given language subtag is invalid",
+                ".d: String is not a valid MIME type: mime parse error: a slash (/) was missing between the type and subtype"
+            ]
+        );
+    }
+
+    #[test]
+    fn lets_registered_formats_replace_builtin_types() {
+        let mut formats = Formats::new();
+        formats.insert(
+            "slug".to_owned(),
+            Rc::new(|_: &Value| Err("Slugs are disabled".to_owned())),
+        );
+        let mut schema = load_with_formats(
+            write_file("builtin-format.strudach.json", &json!({"a": "slug"})),
+            &formats,
+        )
+        .unwrap();
+        assert_eq!(
+            errors(&mut schema, json!({"a": "a-slug"})),
+            [".a: Custom type `slug`: Slugs are disabled"]
+        );
+    }
+
+    #[test]
+    fn parses_temporal_constraints() {
+        assert!(matches!(
+            &parse_temporal_constraints("after 2020-01-01").unwrap()[..],
+            [Constraint::After(Moment::At(date))] if date == "2020-01-01"
+        ));
+        assert!(matches!(
+            &parse_temporal_constraints("not before .event.started").unwrap()[..],
+            [Constraint::NotBefore(Moment::Path(path))] if path == &["event", "started"]
+        ));
+        assert!(matches!(
+            parse_temporal_constraints("in the past").unwrap()[..],
+            [Constraint::Before(Moment::Now)]
+        ));
+        assert!(matches!(
+            parse_temporal_constraints("between 2020-01-01T12:00:00+02:00 and today").unwrap()[..],
+            [
+                Constraint::NotBefore(Moment::At(_)),
+                Constraint::NotAfter(Moment::Now)
+            ]
+        ));
+        for constraints in [
+            "",
+            "after tomorrow",
+            "not before .",
+            "in the present",
+            "before 2020-13-01",
+        ] {
+            assert!(
+                parse_temporal_constraints(constraints).is_err(),
+                "{}",
+                constraints
+            );
+        }
+    }
+
+    #[test]
+    fn checks_timezones() {
+        for timezone in ["Europe/Paris", "UTC", "Z", "+02", "-0530", "+14:00"] {
+            assert_eq!(check_timezone(timezone), None, "{}", timezone);
+        }
+        assert_eq!(
+            check_timezone("europe/paris"),
+            Some("Unknown time zone europe/paris, did you mean Europe/Paris?".to_owned())
+        );
+        for timezone in ["+15:00", "+02:60", "+2", "Mars/Olympus"] {
+            assert!(check_timezone(timezone).is_some(), "{}", timezone);
+        }
+    }
+
+    #[test]
+    fn validates_temporal_types_and_constraints() {
+        let mut schema = load_inline(json!({
+            "a": "duration",
+            "b": "timezone",
+            "c": "date after 2020-01-01",
+            "d": "date not before .c"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": "P1D", "b": "Europe/Paris", "c": "2021-01-01", "d": "2021-01-01"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"a": "P1X", "b": "Mars/Olympus", "c": "2019-01-01", "d": "2018-01-01"})
+            ),
+            [
+                ".a: String is not a valid ISO 8601 duration: Parser Error: P1X",
+                ".b: String is neither an IANA time zone name nor a UTC offset: Mars/Olympus",
+                ".c: Date 2019-01-01 is not after 2020-01-01",
+                ".d: Date 2018-01-01 is before .c (2019-01-01)"
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_colors_and_contrast() {
+        let mut schema = load_inline(json!({
+            "a": "hex color",
+            "b": "opaque color",
+            "c": "named color",
+            "fg": "color",
+            "bg": "color",
+            "(rules)": "contrast between fg and bg is at least 4.5"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": "#fff", "b": "red", "c": "red", "fg": "#000", "bg": "#fff"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"a": "red", "b": "#ff000080", "c": "#fff", "fg": "#777", "bg": "#888"})
+            ),
+            [
+                ".a: Color red is not written #rrggbb or #rgb",
+                ".b: Color #ff000080 has an opacity of 0.5019608, but must be opaque",
+                ".c: Color #fff is not a named color",
+                ".: Contrast between `fg` (#777) and `bg` (#888) is 1.26:1, below the minimum 4.5:1"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_tag_specs() {
+        assert_eq!(
+            parse_tag_spec("a[href][Title]"),
+            Some(("a".to_owned(), vec!["href".to_owned(), "title".to_owned()]))
+        );
+        assert_eq!(parse_tag_spec("P"), Some(("p".to_owned(), Vec::new())));
+        for spec in ["content", "a[", "a[]", "a[href", "a[on click]", ""] {
+            assert_eq!(parse_tag_spec(spec), None, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn locates_start_tags() {
+        assert_eq!(
+            start_tag_offsets(r#"<p><a title="a<b>">x</a><!-- <i> --><span>y</span></p>"#),
+            [0, 3, 36]
+        );
+    }
+
+    #[test]
+    fn splits_documentation_after_allowed_elements() {
+        assert_eq!(
+            split_documentation("html allowing p, em, a[href], the body"),
+            ("html allowing p, em, a[href]", Some("the body"))
+        );
+        assert_eq!(
+            split_documentation("html allowing p, em, content"),
+            ("html allowing p, em", Some("content"))
+        );
+    }
+
+    #[test]
+    fn validates_html_policies() {
+        let mut schema = load_inline(json!({"a": "html allowing p, a[href]"})).unwrap();
+        assert!(errors(&mut schema, json!({"a": "<p><a href=\"x\">z</a></p>"})).is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"a": "<p><a href=\"x\" title=\"a<b\">z</a><em>w</em></p>"})
+            ),
+            [
+                ".a: Attribute `title` of <a> at character 4 is not allowed",
+                ".a: Element <em> at character 33 is not allowed"
+            ]
+        );
+        assert!(load_inline(json!({"a": "html allowing p, blink"})).is_ok());
+        assert!(load_inline(json!({"a": "html allowing blink"})).is_err());
+    }
+
+    #[test]
+    fn parses_url_constraints() {
+        assert!(matches!(
+            &parse_url_constraints("with scheme HTTPS or http and on host *.example.com").unwrap()[..],
+            [Constraint::Schemes(schemes), Constraint::Host(host)]
+                if schemes == &["https", "http"] && host == "*.example.com"
+        ));
+        for constraints in ["", "with scheme", "on example.com", "with scheme https or"] {
+            assert!(
+                parse_url_constraints(constraints).is_err(),
+                "{}",
+                constraints
             );
         }
     }
 
-    return out;
+    #[test]
+    fn validates_url_constraints() {
+        let mut schema = load_inline(json!({
+            "a": "url with scheme https",
+            "b": "url on host *.example.com",
+            "c": "relative url",
+            "d": "url or path"
+        }))
+        .unwrap();
+        assert!(errors(
+            &mut schema,
+            json!({"a": "https://x.org", "b": "https://www.example.com", "c": "../a", "d": "a/b"})
+        )
+        .is_empty());
+        assert_eq!(
+            errors(
+                &mut schema,
+                json!({"a": "http://x.org", "b": "https://example.org", "c": "https://x.org", "d": 3})
+            ),
+            [
+                ".a: URL has scheme http, but only https is allowed",
+                ".b: URL is on host example.org, but must be on *.example.com",
+                ".c: URL is absolute, with scheme https, but must be relative",
+                ".d: Value has type number, which does not match type url or path"
+            ]
+        );
+    }
 }
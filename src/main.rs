@@ -5,7 +5,7 @@ use std::{fs, path::PathBuf, str::FromStr};
 // Usage: strudach [options] (<schema> | -) <input>...
 //        strudach [options] convert <schema> [<output>]
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Usage: strudach [options] convert <schema> <output>
        strudach [options] <schema> <input>...
        
@@ -41,7 +41,6 @@ fn main() {
         };
         if let Err(e) = fs::write(output_file, jsoned) {
             println!("Error writing output: {}", e);
-            return;
         }
     } else {
         let input_files: Vec<PathBuf> = args